@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+
+// Buckets like the ones made by the kohya training scripts: sizes with about the same area and
+// sides multiple of the step, every image goes in the one with the closest aspect ratio
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BucketOptions {
+    // Largest number of pixels of a bucket, like 1024 * 1024. A u64 so that any two sides fit
+    pub area: u64,
+    pub step: u32,
+    pub min_side: u32,
+    pub max_side: u32,
+}
+
+impl Default for BucketOptions {
+    fn default() -> Self {
+        Self {
+            area: 1024 * 1024,
+            step: 64,
+            min_side: 256,
+            max_side: 2048,
+        }
+    }
+}
+
+impl BucketOptions {
+    // Width and height of every bucket, sorted by aspect ratio
+    pub fn sizes(&self) -> Vec<(u32, u32)> {
+        let step = self.step.max(1);
+        let round = |side: u32| (side / step * step).max(step);
+
+        let square = round((self.area as f64).sqrt() as u32).min(round(self.max_side));
+        let mut sizes = vec![(square, square)];
+        let mut width = round(self.min_side);
+        while width <= self.max_side {
+            let height = (self.area / width as u64).min(u32::MAX as u64) as u32;
+            let height = round(height).min(round(self.max_side));
+            if height >= self.min_side {
+                sizes.push((width, height));
+                sizes.push((height, width));
+            }
+            width += step;
+        }
+
+        sizes.sort_by(|a, b| aspect(*a).total_cmp(&aspect(*b)).then(a.cmp(b)));
+        sizes.dedup();
+        sizes
+    }
+}
+
+fn aspect((width, height): (u32, u32)) -> f64 {
+    width as f64 / height.max(1) as f64
+}
+
+fn pixels((width, height): (u32, u32)) -> u64 {
+    width as u64 * height as u64
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Assignment {
+    pub bucket: (u32, u32),
+    // Share of the pixels cut away when the image is scaled to cover the bucket and cropped
+    pub crop_loss: f64,
+}
+
+// The bucket with the closest aspect ratio, the larger one if two are as close
+pub fn assign(size: (u32, u32), buckets: &[(u32, u32)]) -> Option<Assignment> {
+    let image_aspect = aspect(size);
+    let bucket = buckets.iter().copied().min_by(|a, b| {
+        let error_a = (aspect(*a) - image_aspect).abs();
+        let error_b = (aspect(*b) - image_aspect).abs();
+        error_a
+            .total_cmp(&error_b)
+            .then(pixels(*b).cmp(&pixels(*a)))
+    })?;
+
+    let (width, height) = (size.0 as f64, size.1 as f64);
+    let scale = (bucket.0 as f64 / width).max(bucket.1 as f64 / height);
+    let scaled_area = width * scale * height * scale;
+    let crop_loss = 1.0 - pixels(bucket) as f64 / scaled_area;
+    Some(Assignment {
+        bucket,
+        crop_loss: crop_loss.max(0.0),
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct BucketPopulation {
+    pub bucket: (u32, u32),
+    // Positions of the images in the list given to the plan
+    pub images: Vec<usize>,
+    pub mean_crop_loss: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct BucketPlan {
+    // Same order of the image sizes, None for the images that could not be read
+    pub assignments: Vec<Option<Assignment>>,
+    // Only the buckets with at least an image, sorted by aspect ratio
+    pub populations: Vec<BucketPopulation>,
+}
+
+impl BucketPlan {
+    pub fn new(sizes: &[Option<(u32, u32)>], options: &BucketOptions) -> Self {
+        let buckets = options.sizes();
+        let assignments: Vec<Option<Assignment>> = sizes
+            .iter()
+            .map(|size| size.and_then(|size| assign(size, &buckets)))
+            .collect();
+
+        let mut by_bucket: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for (i, assignment) in assignments.iter().enumerate() {
+            if let Some(assignment) = assignment {
+                let position = buckets
+                    .iter()
+                    .position(|b| *b == assignment.bucket)
+                    .unwrap_or(0);
+                by_bucket.entry(position).or_default().push(i);
+            }
+        }
+
+        let populations = by_bucket
+            .into_iter()
+            .map(|(position, images)| {
+                let loss: f64 = images
+                    .iter()
+                    .filter_map(|i| assignments[*i].map(|a| a.crop_loss))
+                    .sum();
+                BucketPopulation {
+                    bucket: buckets[position],
+                    mean_crop_loss: loss / images.len() as f64,
+                    images,
+                }
+            })
+            .collect();
+
+        Self {
+            assignments,
+            populations,
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::fmt;
+
+// A tag longer than this is most likely a sentence, so the caption is treated as prose
+const MAX_TAG_WORDS: usize = 6;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    // Attention weight written as "(tag:1.2)"
+    pub weight: Option<Weight>,
+}
+
+// The text of the weight is written back as it was, so "1.0" and "1.10" are not reformatted
+#[derive(Clone, Debug, PartialEq)]
+pub struct Weight {
+    pub value: f32,
+    pub text: String,
+}
+
+impl Weight {
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let value = text.parse::<f32>().ok()?;
+        Some(Self {
+            value,
+            text: text.to_string(),
+        })
+    }
+}
+
+impl Tag {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            weight: None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let text = text.trim();
+        if let Some(inner) = text.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+            if let Some((name, weight)) = inner.rsplit_once(':') {
+                if let Some(weight) = Weight::parse(weight) {
+                    return Self {
+                        name: name.trim().to_string(),
+                        weight: Some(weight),
+                    };
+                }
+            }
+        }
+        Self::new(text)
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.weight {
+            Some(weight) => write!(f, "({}:{})", self.name, weight.text),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CaptionFormat {
+    pub separator: char,
+    // Trim the tags and collapse the whitespace inside them to a single space
+    pub normalize_whitespace: bool,
+    // Remove the tags that were already in the caption, the first one is kept
+    pub dedup: bool,
+}
+
+impl Default for CaptionFormat {
+    fn default() -> Self {
+        Self {
+            separator: ',',
+            normalize_whitespace: true,
+            dedup: true,
+        }
+    }
+}
+
+impl CaptionFormat {
+    pub fn with_separator(separator: char) -> Self {
+        Self {
+            separator,
+            ..Default::default()
+        }
+    }
+
+    // The separator followed by a space, like "a, b"
+    pub fn joiner(&self) -> String {
+        if self.separator.is_whitespace() {
+            self.separator.to_string()
+        } else {
+            format!("{} ", self.separator)
+        }
+    }
+}
+
+// A caption is either a list of tags, or free text that is never modified
+#[derive(Clone, Debug, PartialEq)]
+pub enum Caption {
+    Tags(Vec<Tag>),
+    Prose(String),
+}
+
+impl Default for Caption {
+    fn default() -> Self {
+        Caption::Tags(Vec::new())
+    }
+}
+
+impl Caption {
+    pub fn parse(text: &str, format: &CaptionFormat) -> Self {
+        if is_prose(text, format.separator) {
+            return Caption::Prose(text.to_string());
+        }
+
+        let mut tags: Vec<Tag> = Vec::new();
+        for part in text.split(format.separator) {
+            let mut tag = Tag::parse(part);
+            if format.normalize_whitespace {
+                tag.name = tag.name.split_whitespace().collect::<Vec<&str>>().join(" ");
+            }
+            // Stray separators leave empty tags behind
+            if tag.name.is_empty() {
+                continue;
+            }
+            if format.dedup && tags.iter().any(|t| t.name == tag.name) {
+                continue;
+            }
+            tags.push(tag);
+        }
+        Caption::Tags(tags)
+    }
+
+    pub fn serialize(&self, format: &CaptionFormat) -> String {
+        match self {
+            Caption::Tags(tags) => tags
+                .iter()
+                .map(|tag| tag.to_string())
+                .collect::<Vec<String>>()
+                .join(&format.joiner()),
+            Caption::Prose(text) => text.clone(),
+        }
+    }
+
+    pub fn is_prose(&self) -> bool {
+        matches!(self, Caption::Prose(_))
+    }
+
+    // Prose captions have no tags
+    pub fn tags(&self) -> &[Tag] {
+        match self {
+            Caption::Tags(tags) => tags,
+            Caption::Prose(_) => &[],
+        }
+    }
+
+    pub fn tags_mut(&mut self) -> Option<&mut Vec<Tag>> {
+        match self {
+            Caption::Tags(tags) => Some(tags),
+            Caption::Prose(_) => None,
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.tags().iter().any(|tag| tag.name == name)
+    }
+}
+
+// Sentences are long and end with a full stop, tags are short
+fn is_prose(text: &str, separator: char) -> bool {
+    let text = text.trim();
+    if text.is_empty() {
+        return false;
+    }
+    if text.ends_with('.') || text.contains(". ") || text.contains('\n') {
+        return true;
+    }
+    text.split(separator)
+        .any(|part| part.split_whitespace().count() > MAX_TAG_WORDS)
+}
@@ -0,0 +1,188 @@
+use std::fmt;
+
+use crate::caption::{Caption, CaptionFormat, Tag};
+
+// A change to the tags of a caption, tags are matched by name ignoring the weight
+#[derive(Clone, Debug, PartialEq)]
+pub enum TagOperation {
+    AddStart(String),
+    AddEnd(String),
+    Remove(String),
+    Replace(String, String),
+    MoveToFront(String),
+    Sort,
+    // Removes the tags that were already in the caption, the first one is kept
+    Dedup,
+}
+
+impl TagOperation {
+    fn apply(&self, tags: &mut Vec<Tag>) {
+        match self {
+            // A tag that is already in the caption is not added again
+            TagOperation::AddStart(tag) => {
+                let tag = Tag::parse(tag);
+                if !tags.iter().any(|t| t.name == tag.name) {
+                    tags.insert(0, tag);
+                }
+            }
+            TagOperation::AddEnd(tag) => {
+                let tag = Tag::parse(tag);
+                if !tags.iter().any(|t| t.name == tag.name) {
+                    tags.push(tag);
+                }
+            }
+            TagOperation::Remove(name) => tags.retain(|tag| tag.name != *name),
+            // The weight is kept unless the new tag has one, if the new tag was already
+            // in the caption the old one is removed
+            TagOperation::Replace(old, new) => {
+                let new = Tag::parse(new);
+                let already_there = new.name != *old && tags.iter().any(|tag| tag.name == new.name);
+                let mut replaced = Vec::with_capacity(tags.len());
+                for tag in tags.drain(..) {
+                    if tag.name != *old {
+                        replaced.push(tag);
+                    } else if !already_there {
+                        replaced.push(Tag {
+                            name: new.name.clone(),
+                            weight: new.weight.clone().or(tag.weight),
+                        });
+                    }
+                }
+                *tags = replaced;
+            }
+            TagOperation::MoveToFront(name) => {
+                if let Some(i) = tags.iter().position(|tag| tag.name == *name) {
+                    let tag = tags.remove(i);
+                    tags.insert(0, tag);
+                }
+            }
+            TagOperation::Sort => tags.sort_by(|a, b| a.name.cmp(&b.name)),
+            TagOperation::Dedup => {
+                let mut kept: Vec<Tag> = Vec::with_capacity(tags.len());
+                for tag in tags.drain(..) {
+                    if !kept.iter().any(|t| t.name == tag.name) {
+                        kept.push(tag);
+                    }
+                }
+                *tags = kept;
+            }
+        }
+    }
+
+    // The tags are compared with the trimmed tags of the captions
+    fn trimmed(self) -> Self {
+        let trim = |tag: String| tag.trim().to_string();
+        match self {
+            TagOperation::AddStart(tag) => TagOperation::AddStart(trim(tag)),
+            TagOperation::AddEnd(tag) => TagOperation::AddEnd(trim(tag)),
+            TagOperation::Remove(tag) => TagOperation::Remove(trim(tag)),
+            TagOperation::Replace(old, new) => TagOperation::Replace(trim(old), trim(new)),
+            TagOperation::MoveToFront(tag) => TagOperation::MoveToFront(trim(tag)),
+            TagOperation::Sort | TagOperation::Dedup => self,
+        }
+    }
+
+    fn tags(&self) -> Vec<&str> {
+        match self {
+            TagOperation::AddStart(tag)
+            | TagOperation::AddEnd(tag)
+            | TagOperation::Remove(tag)
+            | TagOperation::MoveToFront(tag) => vec![tag],
+            TagOperation::Replace(old, new) => vec![old, new],
+            TagOperation::Sort | TagOperation::Dedup => Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for TagOperation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TagOperation::AddStart(tag) => write!(f, "add {} at the start", tag),
+            TagOperation::AddEnd(tag) => write!(f, "add {} at the end", tag),
+            TagOperation::Remove(tag) => write!(f, "remove {}", tag),
+            TagOperation::Replace(old, new) => write!(f, "replace {} with {}", old, new),
+            TagOperation::MoveToFront(tag) => write!(f, "move {} to the front", tag),
+            TagOperation::Sort => write!(f, "sort the tags"),
+            TagOperation::Dedup => write!(f, "remove the duplicated tags"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EditOutcome {
+    Changed(String),
+    Unchanged,
+    // Prose captions have no tags to edit
+    Prose,
+}
+
+#[derive(Clone, Debug)]
+pub struct FileEdit {
+    pub name: String,
+    pub old: String,
+    pub outcome: EditOutcome,
+}
+
+// Operations applied in order to the tags of every caption, used by the tag command and by the gui
+pub struct CaptionEdit {
+    operations: Vec<TagOperation>,
+    format: CaptionFormat,
+}
+
+impl CaptionEdit {
+    pub fn new(operations: Vec<TagOperation>, format: CaptionFormat) -> Result<Self, String> {
+        if operations.is_empty() {
+            return Err("No operation to apply".to_string());
+        }
+        let operations: Vec<TagOperation> = operations.into_iter().map(|op| op.trimmed()).collect();
+        for operation in &operations {
+            if operation.tags().iter().any(|tag| tag.is_empty()) {
+                return Err(format!("Empty tag in \"{}\"", operation));
+            }
+        }
+        Ok(Self { operations, format })
+    }
+
+    pub fn operations(&self) -> &[TagOperation] {
+        &self.operations
+    }
+
+    pub fn apply(&self, caption: &str) -> EditOutcome {
+        // Duplicated tags are removed only by the Dedup operation
+        let format = CaptionFormat {
+            dedup: false,
+            ..self.format
+        };
+        let original = match Caption::parse(caption, &format) {
+            Caption::Tags(tags) => tags,
+            Caption::Prose(_) => return EditOutcome::Prose,
+        };
+
+        let mut tags = original.clone();
+        for operation in &self.operations {
+            operation.apply(&mut tags);
+        }
+
+        // The caption is not rewritten only to fix its formatting
+        if tags == original {
+            EditOutcome::Unchanged
+        } else {
+            EditOutcome::Changed(Caption::Tags(tags).serialize(&self.format))
+        }
+    }
+
+    // Takes the name and the caption of every image
+    pub fn plan<'a>(
+        &self,
+        captions: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Vec<FileEdit> {
+        captions
+            .into_iter()
+            .map(|(name, caption)| FileEdit {
+                name: name.to_string(),
+                old: caption.to_string(),
+                outcome: self.apply(caption),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,84 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const BACKUP_EXTENSION: &str = "bak";
+
+// Every caption is written through this, so that a crash or a full disk never leaves a truncated caption:
+// the new caption is written to a temporary file that replaces the old one only once it is on disk
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CaptionWriter {
+    // Keep the previous caption in "<caption>.bak"
+    pub keep_backup: bool,
+}
+
+impl CaptionWriter {
+    pub fn new(keep_backup: bool) -> Self {
+        Self { keep_backup }
+    }
+
+    pub fn write(&self, path: &Path, caption: &str) -> io::Result<()> {
+        let tmp = tmp_path(path);
+        if let Err(e) = write_synced(&tmp, caption) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+
+        if self.keep_backup && path.is_file() {
+            if let Err(e) = fs::copy(path, backup_path(path)) {
+                let _ = fs::remove_file(&tmp);
+                return Err(e);
+            }
+        }
+
+        if let Err(e) = fs::rename(&tmp, path) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+
+        sync_parent(path);
+        Ok(())
+    }
+}
+
+// "cat.txt" is backed up to "cat.txt.bak"
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(BACKUP_EXTENSION);
+    path.with_file_name(name)
+}
+
+// The temporary file is in the same folder, renames across file systems are not atomic
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
+
+fn write_synced(path: &Path, content: &str) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()
+}
+
+// On unix the rename itself is durable only after the folder is synced
+#[cfg(unix)]
+fn sync_parent(path: &Path) {
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        if let Ok(dir) = File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) {}
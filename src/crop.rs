@@ -0,0 +1,217 @@
+use image::{DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::resize::{save_image, ResizeFilter};
+
+// The crop of an image is saved next to it in a json file with the same stem, so renaming the
+// folder keeps them together
+pub const CROP_EXTENSION: &str = "crop";
+
+// Images whose aspect ratio differs less than this are considered the same image at another size
+const ASPECT_TOLERANCE: f64 = 0.01;
+
+pub fn crop_path(img_path: &Path) -> PathBuf {
+    img_path.with_extension(CROP_EXTENSION)
+}
+
+// Part of an image, in pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CropRect {
+    // The biggest rect with the aspect ratio that fits in the image, in the center
+    pub fn centered((width, height): (u32, u32), (aspect_w, aspect_h): (u32, u32)) -> Self {
+        let aspect = aspect_w as f64 / aspect_h.max(1) as f64;
+        let (crop_width, crop_height) = if width as f64 / height.max(1) as f64 > aspect {
+            ((height as f64 * aspect).round() as u32, height)
+        } else {
+            (width, (width as f64 / aspect).round() as u32)
+        };
+        let (crop_width, crop_height) = (crop_width.clamp(1, width), crop_height.clamp(1, height));
+        Self {
+            x: (width - crop_width) / 2,
+            y: (height - crop_height) / 2,
+            width: crop_width,
+            height: crop_height,
+        }
+    }
+}
+
+// How an image was cropped, it can be applied again to the original or to a bigger version of it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CropRecord {
+    // File name of the image the crop was made on
+    pub image: String,
+    // Size of that image, the rect is relative to it
+    pub original: (u32, u32),
+    pub rect: CropRect,
+    // The crop is scaled to this size, None keeps the cropped pixels as they are
+    pub size: Option<(u32, u32)>,
+}
+
+impl CropRecord {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid crop {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    // The same crop on the image with another size, it must have the same aspect ratio
+    pub fn scaled_to(&self, (width, height): (u32, u32)) -> Result<Self, String> {
+        let (original_width, original_height) = self.original;
+        let aspect = width as f64 / height.max(1) as f64;
+        let original_aspect = original_width as f64 / original_height.max(1) as f64;
+        if (aspect / original_aspect - 1.0).abs() > ASPECT_TOLERANCE {
+            return Err(format!(
+                "{}x{} has not the aspect ratio of {}x{}, the size of the cropped image",
+                width, height, original_width, original_height
+            ));
+        }
+
+        let scale_x = width as f64 / original_width.max(1) as f64;
+        let scale_y = height as f64 / original_height.max(1) as f64;
+        let scale = |value: u32, scale: f64| (value as f64 * scale).round() as u32;
+        let x = scale(self.rect.x, scale_x).min(width - 1);
+        let y = scale(self.rect.y, scale_y).min(height - 1);
+        let rect = CropRect {
+            x,
+            y,
+            width: scale(self.rect.width, scale_x).clamp(1, width - x),
+            height: scale(self.rect.height, scale_y).clamp(1, height - y),
+        };
+        Ok(Self {
+            image: self.image.clone(),
+            original: (width, height),
+            rect,
+            size: self.size,
+        })
+    }
+
+    // Size of the cropped image
+    pub fn cropped_size(&self) -> (u32, u32) {
+        self.size.unwrap_or((self.rect.width, self.rect.height))
+    }
+
+    // The next crop is made on the image cropped with this record, the result is the same crop
+    // made directly on the first original
+    pub fn followed_by(&self, next: &CropRecord) -> Self {
+        let (width, height) = self.cropped_size();
+        let scale_x = self.rect.width as f64 / width.max(1) as f64;
+        let scale_y = self.rect.height as f64 / height.max(1) as f64;
+        let scale = |value: u32, scale: f64| (value as f64 * scale).round() as u32;
+        let x = (self.rect.x + scale(next.rect.x, scale_x)).min(self.rect.x + self.rect.width - 1);
+        let y = (self.rect.y + scale(next.rect.y, scale_y)).min(self.rect.y + self.rect.height - 1);
+        let rect = CropRect {
+            x,
+            y,
+            width: scale(next.rect.width, scale_x).clamp(1, self.rect.x + self.rect.width - x),
+            height: scale(next.rect.height, scale_y).clamp(1, self.rect.y + self.rect.height - y),
+        };
+        // A scaled first crop has other pixels than the original, the second one keeps its size
+        let scaled = (width, height) != (self.rect.width, self.rect.height);
+        let size = match next.size {
+            None if scaled => Some((next.rect.width, next.rect.height)),
+            size => size,
+        };
+        Self {
+            image: self.image.clone(),
+            original: self.original,
+            rect,
+            size,
+        }
+    }
+
+    // The image must have the original size
+    pub fn apply(&self, img: &DynamicImage, filter: ResizeFilter) -> DynamicImage {
+        let CropRect {
+            x,
+            y,
+            width,
+            height,
+        } = self.rect;
+        let cropped = img.crop_imm(x, y, width, height);
+        match self.size {
+            Some((width, height)) if (width, height) != cropped.dimensions() => {
+                cropped.resize_exact(width, height, filter.filter_type())
+            }
+            _ => cropped,
+        }
+    }
+}
+
+// Crops the source image into the output path and saves the record next to it, the record is
+// scaled first if the source has another size. The source and the output can be the same file.
+// If the source was already cropped the saved record is relative to its first original
+pub fn crop_file(
+    source: &Path,
+    output: &Path,
+    record: &CropRecord,
+    filter: ResizeFilter,
+) -> Result<CropRecord, String> {
+    let img =
+        image::open(source).map_err(|e| format!("Failed to open {}: {}", source.display(), e))?;
+    let mut record = record.scaled_to(img.dimensions())?;
+    if let Some(name) = source.file_name() {
+        record.image = name.to_string_lossy().to_string();
+    }
+    let cropped = record.apply(&img, filter);
+
+    let previous = crop_path(source);
+    if previous.is_file() {
+        let previous_record = CropRecord::load(&previous)?;
+        if previous_record.cropped_size() != img.dimensions() {
+            return Err(format!(
+                "{} is not the crop of this image, remove it to crop again",
+                previous.display()
+            ));
+        }
+        record = previous_record.followed_by(&record);
+    }
+
+    save_replacing(&cropped, output)?;
+    record.save(&crop_path(output))?;
+    Ok(record)
+}
+
+// The image is written to a temporary file that replaces the output only once it is complete,
+// so a failed crop never leaves a truncated image
+fn save_replacing(img: &DynamicImage, output: &Path) -> Result<(), String> {
+    let tmp = tmp_path(output);
+    let saved = save_image(img, &tmp).and_then(|()| {
+        fs::rename(&tmp, output)
+            .map_err(|e| format!("Failed to replace {}: {}", output.display(), e))
+    });
+    if saved.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    saved
+}
+
+// In the same folder, renames across file systems are not atomic. The extension is kept because
+// it chooses the format of the image
+fn tmp_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!(
+            "{}.{}.tmp.{}",
+            stem,
+            std::process::id(),
+            ext.to_string_lossy()
+        ),
+        None => format!("{}.{}.tmp", stem, std::process::id()),
+    };
+    path.with_file_name(name)
+}
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::DirEntry;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::caption_writer::CaptionWriter;
+
+// Extensions of the images that can be decoded, gifs are shown as their first frame
+pub const IMAGE_EXTENSIONS: [&str; 8] = ["png", "jpg", "jpeg", "webp", "bmp", "gif", "tif", "tiff"];
+
+pub const CAPTION_EXTENSION: &str = "txt";
+
+pub fn is_image(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()),
+        None => false,
+    }
+}
+
+pub fn is_caption(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == CAPTION_EXTENSION)
+}
+
+// The caption is stored in a txt file with the same stem of the image
+pub fn caption_path(img_path: &Path) -> PathBuf {
+    img_path.with_extension(CAPTION_EXTENSION)
+}
+
+pub fn get_files_in_folder(path: &Path, filter: Option<&dyn Fn(&Path) -> bool>) -> Vec<DirEntry> {
+    let files = fs::read_dir(path);
+    if files.is_err() {
+        println!("Failed to read directory");
+        return Vec::new();
+    }
+    let files = files.unwrap();
+    let mut good_files = Vec::new();
+
+    if let Some(filter) = filter {
+        for file in files.flatten() {
+            if filter(&file.path()) {
+                good_files.push(file);
+            }
+        }
+    } else {
+        for file in files.flatten() {
+            good_files.push(file);
+        }
+    }
+
+    // if we do not save the state of the directory, the same file will be renamed multiple times
+    good_files
+}
+
+// An image of the dataset together with its caption sidecar
+#[derive(Clone, Debug)]
+pub struct DatasetEntry {
+    image: PathBuf,
+    caption: PathBuf,
+    has_caption: bool,
+    metadata: Option<Metadata>,
+}
+
+impl DatasetEntry {
+    pub fn new(image: &Path) -> Self {
+        let caption = caption_path(image);
+        Self {
+            image: image.to_path_buf(),
+            has_caption: caption.is_file(),
+            caption,
+            metadata: fs::metadata(image).ok(),
+        }
+    }
+
+    // The file name without the extension, it is shared by the image and its caption
+    pub fn name(&self) -> String {
+        self.image
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn extension(&self) -> String {
+        self.image
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn image_path(&self) -> &Path {
+        &self.image
+    }
+
+    pub fn caption_path(&self) -> &Path {
+        &self.caption
+    }
+
+    pub fn has_caption(&self) -> bool {
+        self.has_caption
+    }
+
+    // Size of the image file in bytes
+    pub fn size(&self) -> u64 {
+        self.metadata.as_ref().map_or(0, |metadata| metadata.len())
+    }
+
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.metadata.as_ref()?.modified().ok()
+    }
+
+    pub fn read_image(&self) -> io::Result<Vec<u8>> {
+        fs::read(&self.image)
+    }
+
+    // A missing caption file is treated as an empty caption
+    pub fn read_caption(&self) -> String {
+        fs::read_to_string(&self.caption).unwrap_or_default()
+    }
+
+    pub fn write_caption(&mut self, caption: &str, writer: &CaptionWriter) -> io::Result<()> {
+        writer.write(&self.caption, caption)?;
+        self.has_caption = true;
+        Ok(())
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    let name = path.file_name().unwrap_or_default();
+    name.to_string_lossy().to_string()
+}
+
+// All the images contained in a folder, sorted by file name
+#[derive(Clone, Debug)]
+pub struct Dataset {
+    root: PathBuf,
+    entries: Vec<DatasetEntry>,
+    // Caption files that do not belong to any image
+    orphan_captions: Vec<PathBuf>,
+}
+
+impl Dataset {
+    pub fn open(root: &Path) -> io::Result<Self> {
+        let mut images = Vec::new();
+        let mut captions = Vec::new();
+        for file in fs::read_dir(root)?.flatten() {
+            let path = file.path();
+            if !path.is_file() {
+                continue;
+            }
+            if is_image(&path) {
+                images.push(path);
+            } else if is_caption(&path) {
+                captions.push(path);
+            }
+        }
+        images.sort();
+        captions.sort();
+
+        let entries: Vec<DatasetEntry> = images.iter().map(|img| DatasetEntry::new(img)).collect();
+        let orphan_captions = captions
+            .into_iter()
+            .filter(|caption| !entries.iter().any(|entry| entry.caption == *caption))
+            .collect();
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            entries,
+            orphan_captions,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn entries(&self) -> &[DatasetEntry] {
+        &self.entries
+    }
+
+    pub fn entries_mut(&mut self) -> &mut [DatasetEntry] {
+        &mut self.entries
+    }
+
+    pub fn into_entries(self) -> Vec<DatasetEntry> {
+        self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&DatasetEntry> {
+        self.entries.iter().find(|entry| entry.name() == name)
+    }
+
+    // Images are told apart by their name, like the captions, so the commands that pair every
+    // image with its caption can't work on a folder with cat.png and cat.jpg
+    pub fn check_unique_names(&self) -> Result<(), String> {
+        let mut names: HashMap<String, &Path> = HashMap::new();
+        for entry in &self.entries {
+            if let Some(other) = names.insert(entry.name(), entry.image_path()) {
+                return Err(format!(
+                    "{} and {} have the same name and would share the caption {}",
+                    file_name(other),
+                    file_name(entry.image_path()),
+                    file_name(entry.caption_path())
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn orphan_captions(&self) -> &[PathBuf] {
+        &self.orphan_captions
+    }
+
+    // Every caption file in the folder, with or without an image. Two images with the same name
+    // share their caption, it is listed once
+    pub fn caption_files(&self) -> Vec<PathBuf> {
+        let mut captions: Vec<PathBuf> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.has_caption)
+            .map(|entry| entry.caption.clone())
+            .collect();
+        captions.extend(self.orphan_captions.iter().cloned());
+        captions.sort();
+        captions.dedup();
+        captions
+    }
+}
@@ -0,0 +1,15 @@
+pub mod buckets;
+pub mod caption;
+pub mod caption_edit;
+pub mod caption_writer;
+pub mod crop;
+pub mod dataset;
+pub mod query;
+pub mod rename;
+pub mod resize;
+pub mod resolution;
+pub mod stats;
+pub mod substitute;
+pub mod vocabulary;
+
+pub use dataset::{Dataset, DatasetEntry};
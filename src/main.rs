@@ -1,164 +1,1563 @@
+use ai_utils::buckets::{BucketOptions, BucketPlan};
+use ai_utils::caption::CaptionFormat;
+use ai_utils::caption_edit::{CaptionEdit, EditOutcome, TagOperation};
+use ai_utils::caption_writer::CaptionWriter;
+use ai_utils::crop::{crop_file, CropRecord, CROP_EXTENSION};
+use ai_utils::dataset::{caption_path, get_files_in_folder, IMAGE_EXTENSIONS};
+use ai_utils::query::Query;
+use ai_utils::rename::{Journal, RenameOptions, RenameOrder, RenamePlan};
+use ai_utils::resize::{
+    parse_color, parse_target_size, resize_all, resize_file, run_parallel, ResizeFilter,
+    ResizeMode, ResizeOptions, Resized,
+};
+use ai_utils::resolution::Resolution;
+use ai_utils::stats::DatasetStats;
+use ai_utils::substitute::{MatchMode, Substitution};
+use ai_utils::vocabulary::Vocabulary;
+use ai_utils::{Dataset, DatasetEntry};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use colored::Colorize;
 use directories::UserDirs;
 use rfd::FileDialog;
-use std::env;
 use std::fs;
-use std::fs::DirEntry;
-use std::fs::File;
+use std::io;
 use std::io::Write;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::process::ExitCode;
+use std::thread;
 
 mod tag_gui;
-fn main() {
-    let args: Vec<String> = env::args().collect();
+use tag_gui::image_buffer::BufferOptions;
+use tag_gui::shortcuts::Shortcuts;
+use tag_gui::{Autosave, GuiOptions};
 
-    if args.len() == 1 {
-        println!("Usage: rn <path> -> Renames all the files in the folder to the folder\n gui <path> -> Starts the GUI for tagging");
-        return;
+#[derive(Parser)]
+#[command(
+    name = "ai_utils",
+    version,
+    about = "Small set of tools to assist in the preparation of image datasets"
+)]
+struct Cli {
+    /// Print what would be changed without modifying any file
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Renames all the files in the folder to "<folder name> (x)", images keep the same name of their captions
+    Rn {
+        /// Folder containing the files to rename
+        #[arg(value_parser = parse_dir, required_unless_present = "undo")]
+        path: Option<PathBuf>,
+        /// Template of the new names, {name} is the folder name and {n} the counter
+        #[arg(long, default_value = "{name} ({n})")]
+        template: String,
+        /// Minimum number of digits of the counter, padded with zeros
+        #[arg(long, default_value_t = 0)]
+        pad: usize,
+        /// First value of the counter
+        #[arg(long, default_value_t = 0)]
+        start: usize,
+        /// Order in which the files are numbered
+        #[arg(long, value_enum, default_value_t = RenameOrder::Natural)]
+        order: RenameOrder,
+        /// Move every file to a temporary name first, needed when the new names are already in use
+        #[arg(long)]
+        two_phase: bool,
+        /// Where to write the journal of the renames, by default it is saved inside the folder
+        #[arg(long, value_parser = parse_path)]
+        journal: Option<PathBuf>,
+        /// Restore the original names using the journal written by a previous rename
+        #[arg(long, value_parser = parse_path, conflicts_with_all = ["path", "journal"])]
+        undo: Option<PathBuf>,
+    },
+    /// Starts the GUI for tagging, if no folder is given a folder picker is opened
+    Gui {
+        /// Folder containing the images to caption
+        #[arg(value_parser = parse_dir)]
+        path: Option<PathBuf>,
+        /// Keep a .bak copy of every caption before overwriting it
+        #[arg(long)]
+        backup: bool,
+        /// Separator between the tags of a caption
+        #[arg(long, default_value_t = ',')]
+        separator: char,
+        /// Csv file with a tag per line (like the danbooru tag list), used for autocompletion
+        #[arg(long, value_parser = parse_path)]
+        tag_list: Option<PathBuf>,
+        /// Number of images decoded in advance before and after the current one
+        #[arg(long, default_value_t = 8)]
+        prefetch: usize,
+        /// Memory used at most by the decoded images, in MB
+        #[arg(long, default_value_t = 1024)]
+        cache_mb: usize,
+        /// Json file with the keyboard shortcuts and the quick tags of the number keys
+        #[arg(long, value_parser = parse_path)]
+        shortcuts: Option<PathBuf>,
+        /// When the edited captions are saved without pressing Save, manual saves only with Save
+        #[arg(long, value_enum, value_delimiter = ',', default_value = "navigate")]
+        autosave: Vec<AutosaveTrigger>,
+        /// Seconds without changes before the idle autosave
+        #[arg(long, default_value_t = 30.0, value_parser = parse_seconds)]
+        autosave_delay: f64,
+        /// Expected size of the images: 512x512, min:1024x1024, aspect:1:1,4:3 or buckets:1024x1024,1152x896
+        #[arg(long, default_value = "512x512", value_parser = Resolution::parse)]
+        resolution: Resolution,
+        /// Folder where the crop tool writes the cropped images, without it they replace the originals
+        #[arg(long, value_parser = parse_path)]
+        crop_output: Option<PathBuf>,
+    },
+    /// Replaces a string in every caption file of the folder
+    Sub {
+        /// Folder containing the caption files
+        #[arg(value_parser = parse_dir)]
+        path: PathBuf,
+        /// String, regex or tag to replace
+        old: String,
+        /// String to replace it with
+        new: String,
+        /// How the string to replace is matched
+        #[arg(long, value_enum, default_value_t = MatchMode::Plain)]
+        mode: MatchMode,
+        /// Ignore the case when matching
+        #[arg(short, long)]
+        ignore_case: bool,
+        /// Separator between the tags of a caption, used by the tag mode
+        #[arg(long, default_value_t = ',')]
+        separator: char,
+        /// Show the changes of every file and ask for confirmation before saving
+        #[arg(long)]
+        preview: bool,
+        /// Keep a .bak copy of every caption before overwriting it
+        #[arg(long)]
+        backup: bool,
+    },
+    /// Adds, removes, replaces and reorders tags in many captions at once.
+    /// The operations are applied in the order: remove, replace, add, move to the front, dedup, sort
+    Tag {
+        /// Folder containing the images and the captions
+        #[arg(value_parser = parse_dir)]
+        path: PathBuf,
+        /// Only edit the captions matching this query, written as in the gui filter box
+        #[arg(long)]
+        filter: Option<String>,
+        #[command(flatten)]
+        operations: TagOperationArgs,
+        /// Separator between the tags of a caption
+        #[arg(long, default_value_t = ',')]
+        separator: char,
+        /// Show the changes of every file and ask for confirmation before saving
+        #[arg(long)]
+        preview: bool,
+        /// Keep a .bak copy of every caption before overwriting it
+        #[arg(long)]
+        backup: bool,
+    },
+    /// Shows how often every tag is used in the captions of the folder
+    Stats {
+        /// Folder containing the images and the captions
+        #[arg(value_parser = parse_dir)]
+        path: PathBuf,
+        /// Number of tags and pairs of tags shown in the table
+        #[arg(long, default_value_t = 20)]
+        top: usize,
+        /// Output format, csv only contains the tag frequencies
+        #[arg(long, value_enum, default_value_t = StatsFormat::Table)]
+        format: StatsFormat,
+        /// Separator between the tags of a caption
+        #[arg(long, default_value_t = ',')]
+        separator: char,
+    },
+    /// Resizes or crops every image of the folder into another folder, the captions are copied along
+    Resize {
+        /// Folder containing the images
+        #[arg(value_parser = parse_dir)]
+        path: PathBuf,
+        /// Folder where the resized images and their captions are written
+        #[arg(short, long, value_parser = parse_path)]
+        output: PathBuf,
+        /// Target size, like 1024x768 or 1024 for a square
+        #[arg(long, value_parser = parse_target_size)]
+        size: (u32, u32),
+        #[arg(long, value_enum, default_value_t = ResizeMode::Fit)]
+        mode: ResizeMode,
+        #[arg(long, value_enum, default_value_t = ResizeFilter::Lanczos3)]
+        filter: ResizeFilter,
+        /// Color of the padding in pad mode, like #000000 or #ffffff00
+        #[arg(long, default_value = "#000000", value_parser = parse_color)]
+        pad_color: [u8; 4],
+        /// Number of images resized at the same time, by default one per core
+        #[arg(long)]
+        threads: Option<usize>,
+    },
+    /// Shows in which aspect ratio bucket every image goes for training and how much of it is cropped
+    Buckets {
+        /// Folder containing the images
+        #[arg(value_parser = parse_dir)]
+        path: PathBuf,
+        /// Largest bucket, the others have about the same number of pixels. Like 1024 or 1216x832
+        #[arg(long, default_value = "1024", value_parser = parse_target_size)]
+        area: (u32, u32),
+        /// The sides of the buckets are multiple of this
+        #[arg(long, default_value_t = 64)]
+        step: u32,
+        #[arg(long, default_value_t = 256)]
+        min_side: u32,
+        #[arg(long, default_value_t = 2048)]
+        max_side: u32,
+        /// Write every image cropped to its bucket in <OUTPUT>/<width>x<height>, with its caption
+        #[arg(short, long, value_parser = parse_path)]
+        output: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = ResizeFilter::Lanczos3)]
+        filter: ResizeFilter,
+        /// Number of images written at the same time, by default one per core
+        #[arg(long)]
+        threads: Option<usize>,
+    },
+    /// Crops the original images again in the same way as the crops made with the gui, using the .crop files
+    Crop {
+        /// Folder containing the cropped images and their .crop files
+        #[arg(value_parser = parse_dir)]
+        path: PathBuf,
+        /// Folder containing the original images, they can be bigger than the ones that were cropped
+        #[arg(long, value_parser = parse_dir)]
+        originals: PathBuf,
+        /// Folder where the new crops and the captions are written
+        #[arg(short, long, value_parser = parse_path)]
+        output: PathBuf,
+        /// Keep the cropped pixels of the originals instead of scaling them to the size of the first crop
+        #[arg(long)]
+        full_resolution: bool,
+        #[arg(long, value_enum, default_value_t = ResizeFilter::Lanczos3)]
+        filter: ResizeFilter,
+        /// Number of images cropped at the same time, by default one per core
+        #[arg(long)]
+        threads: Option<usize>,
+    },
+}
+
+#[derive(Args)]
+struct TagOperationArgs {
+    /// Tag to remove
+    #[arg(long, value_name = "TAG")]
+    remove: Vec<String>,
+    /// Tag to replace and the tag replacing it
+    #[arg(long, num_args = 2, value_names = ["OLD", "NEW"])]
+    replace: Vec<String>,
+    /// Tag to add at the start of the caption
+    #[arg(long, value_name = "TAG")]
+    add_start: Vec<String>,
+    /// Tag to add at the end of the caption
+    #[arg(long, value_name = "TAG")]
+    add: Vec<String>,
+    /// Tag to move to the start of the caption
+    #[arg(long, value_name = "TAG")]
+    move_front: Vec<String>,
+    /// Remove the duplicated tags
+    #[arg(long)]
+    dedup: bool,
+    /// Sort the tags alphabetically
+    #[arg(long)]
+    sort: bool,
+}
+
+impl TagOperationArgs {
+    fn into_operations(self) -> Vec<TagOperation> {
+        let mut operations: Vec<TagOperation> =
+            self.remove.into_iter().map(TagOperation::Remove).collect();
+        for pair in self.replace.chunks(2) {
+            operations.push(TagOperation::Replace(pair[0].clone(), pair[1].clone()));
+        }
+        // Added in reverse so the tags end up in the order they were written
+        operations.extend(self.add_start.into_iter().rev().map(TagOperation::AddStart));
+        operations.extend(self.add.into_iter().map(TagOperation::AddEnd));
+        operations.extend(
+            self.move_front
+                .into_iter()
+                .rev()
+                .map(TagOperation::MoveToFront),
+        );
+        if self.dedup {
+            operations.push(TagOperation::Dedup);
+        }
+        if self.sort {
+            operations.push(TagOperation::Sort);
+        }
+        operations
     }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum AutosaveTrigger {
+    // Moving to another image
+    Navigate,
+    // Some time without changes
+    Idle,
+    // Closing the window
+    Close,
+    Manual,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum StatsFormat {
+    Table,
+    Json,
+    Csv,
+}
 
-    let command = &args[1];
+fn main() -> ExitCode {
+    let cli = Cli::parse();
 
-    if command == "rn" {
-        let file_path = &args[2];
-        let name = file_path.split('\\').last().unwrap();
-        println!("{} to {}", name, file_path);
-        rename_file(file_path, name);
-        return;
+    let result = match cli.command {
+        Command::Rn {
+            path,
+            template,
+            pad,
+            start,
+            order,
+            two_phase,
+            journal,
+            undo,
+        } => match undo {
+            Some(undo) => undo_rename_command(&undo, cli.dry_run),
+            None => {
+                let path = path.unwrap();
+                match dir_name(&path) {
+                    Some(name) => {
+                        let options = RenameOptions {
+                            template,
+                            name,
+                            pad,
+                            start,
+                            order,
+                        };
+                        rename_command(&path, &options, two_phase, journal, cli.dry_run)
+                    }
+                    None => Err(format!("Impossible to get the name of {}", path.display())),
+                }
+            }
+        },
+        Command::Gui {
+            path,
+            backup,
+            separator,
+            tag_list,
+            prefetch,
+            cache_mb,
+            shortcuts,
+            autosave,
+            autosave_delay,
+            resolution,
+            crop_output,
+        } => {
+            let options = GuiOptions {
+                read_only: cli.dry_run,
+                writer: CaptionWriter::new(backup),
+                caption_format: CaptionFormat::with_separator(separator),
+                buffer: BufferOptions::new(prefetch, cache_mb),
+                autosave: Autosave {
+                    on_navigate: autosave.contains(&AutosaveTrigger::Navigate),
+                    idle: autosave
+                        .contains(&AutosaveTrigger::Idle)
+                        .then_some(autosave_delay),
+                    on_close: autosave.contains(&AutosaveTrigger::Close),
+                },
+                resolution,
+                crop_output,
+            };
+            gui_command(path, tag_list, shortcuts, options)
+        }
+        Command::Sub {
+            path,
+            old,
+            new,
+            mode,
+            ignore_case,
+            separator,
+            preview,
+            backup,
+        } => {
+            let format = CaptionFormat::with_separator(separator);
+            Substitution::new(&old, &new, mode, ignore_case, format).and_then(|sub| {
+                substitute_command(
+                    &path,
+                    &sub,
+                    preview,
+                    CaptionWriter::new(backup),
+                    cli.dry_run,
+                )
+            })
+        }
+        Command::Tag {
+            path,
+            filter,
+            operations,
+            separator,
+            preview,
+            backup,
+        } => {
+            let format = CaptionFormat::with_separator(separator);
+            let filter = filter.map(|query| Query::parse(&query)).transpose();
+            let edit = CaptionEdit::new(operations.into_operations(), format);
+            filter.and_then(|filter| {
+                edit.and_then(|edit| {
+                    tag_command(
+                        &path,
+                        filter.as_ref(),
+                        &edit,
+                        &format,
+                        preview,
+                        CaptionWriter::new(backup),
+                        cli.dry_run,
+                    )
+                })
+            })
+        }
+        Command::Stats {
+            path,
+            top,
+            format,
+            separator,
+        } => stats_command(
+            &path,
+            top,
+            format,
+            &CaptionFormat::with_separator(separator),
+        ),
+        Command::Resize {
+            path,
+            output,
+            size: (width, height),
+            mode,
+            filter,
+            pad_color,
+            threads,
+        } => {
+            let options = ResizeOptions {
+                mode,
+                width,
+                height,
+                filter,
+                pad_color,
+            };
+            resize_command(
+                &path,
+                &output,
+                &options,
+                threads_or_cores(threads),
+                cli.dry_run,
+            )
+        }
+        Command::Buckets {
+            path,
+            area: (width, height),
+            step,
+            min_side,
+            max_side,
+            output,
+            filter,
+            threads,
+        } => {
+            let options = BucketOptions {
+                area: width as u64 * height as u64,
+                step,
+                min_side,
+                max_side,
+            };
+            buckets_command(
+                &path,
+                &options,
+                output.as_deref(),
+                filter,
+                threads_or_cores(threads),
+                cli.dry_run,
+            )
+        }
+        Command::Crop {
+            path,
+            originals,
+            output,
+            full_resolution,
+            filter,
+            threads,
+        } => crop_command(
+            &path,
+            &originals,
+            &output,
+            full_resolution,
+            filter,
+            threads_or_cores(threads),
+            cli.dry_run,
+        ),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e.red());
+            ExitCode::FAILURE
+        }
     }
+}
+
+fn rename_command(
+    path: &Path,
+    options: &RenameOptions,
+    two_phase: bool,
+    journal_path: Option<PathBuf>,
+    dry_run: bool,
+) -> Result<(), String> {
+    println!("{} to {}", options.name, path.display());
+
+    let plan = RenamePlan::for_folder(path, options)?;
+    let journal_path = journal_path.unwrap_or_else(|| Journal::default_path(path));
+    execute_rename_plan(&plan, two_phase, Some(&journal_path), dry_run)
+}
 
-    if command == "gui" {
-        if args.len() < 3 {
+fn undo_rename_command(journal_path: &Path, dry_run: bool) -> Result<(), String> {
+    let journal = Journal::load(journal_path)?;
+    let plan = journal.undo_plan();
+    // Undoing never needs a journal, the original one already describes the renames
+    execute_rename_plan(&plan, true, None, dry_run)?;
+    if !dry_run {
+        println!("Restored {} files", plan.renames().len());
+    }
+    Ok(())
+}
+
+fn execute_rename_plan(
+    plan: &RenamePlan,
+    two_phase: bool,
+    journal_path: Option<&Path>,
+    dry_run: bool,
+) -> Result<(), String> {
+    if plan.is_empty() {
+        println!("Nothing to rename");
+        return Ok(());
+    }
+
+    for rename in plan.renames() {
+        println!(
+            "OLD: {}\nNEW: {}\n",
+            rename.from.display().to_string().red(),
+            rename.to.display().to_string().green()
+        );
+    }
+
+    let collisions = plan.collisions();
+    for collision in &collisions {
+        println!("{} {}", "Collision:".yellow(), collision.display());
+    }
+
+    if dry_run {
+        if plan.needs_two_phase() && !two_phase {
+            println!(
+                "{}",
+                "New names are already in use, --two-phase is needed".yellow()
+            );
+        }
+        if !collisions.is_empty() {
+            return Err(format!(
+                "{} renames would overwrite existing files",
+                collisions.len()
+            ));
+        }
+        return Ok(());
+    }
+
+    let (journal, result) = plan.execute(two_phase);
+    if let Some(journal_path) = journal_path {
+        if !journal.renames.is_empty() {
+            journal.save(journal_path)?;
+            println!("Journal saved to {}", journal_path.display());
+        }
+    }
+    result
+}
+
+fn gui_command(
+    path: Option<PathBuf>,
+    tag_list: Option<PathBuf>,
+    shortcuts: Option<PathBuf>,
+    options: GuiOptions,
+) -> Result<(), String> {
+    let path = match path {
+        Some(path) => path,
+        None => {
             println!("Select file folder");
-            let file_path = FileDialog::new()
-                .set_directory(
-                    UserDirs::new()
-                        .unwrap()
-                        .desktop_dir()
-                        .unwrap()
-                        .to_str()
-                        .unwrap(),
-                )
-                .pick_folder();
-            if file_path.is_none() {
-                println!("No folder selected");
-                return;
+            let mut dialog = FileDialog::new();
+            if let Some(desktop) = UserDirs::new().as_ref().and_then(|dirs| dirs.desktop_dir()) {
+                dialog = dialog.set_directory(desktop);
+            }
+            dialog.pick_folder().ok_or("No folder selected")?
+        }
+    };
+    start_tagging_gui(&path, tag_list.as_deref(), shortcuts.as_deref(), options)
+}
+
+fn substitute_command(
+    path: &Path,
+    substitution: &Substitution,
+    preview: bool,
+    writer: CaptionWriter,
+    dry_run: bool,
+) -> Result<(), String> {
+    let dataset = Dataset::open(path).map_err(|e| format!("Failed to read directory: {}", e))?;
+    let changes = plan_substitution(&dataset.caption_files(), substitution);
+    if changes.is_empty() {
+        println!("No caption matches");
+        return Ok(());
+    }
+
+    if preview || dry_run {
+        for (file, old_caption, new_caption) in &changes {
+            println!("{}", file.display().to_string().bold());
+            print_diff(old_caption, new_caption);
+            println!();
+        }
+        println!("{} files would be modified", changes.len());
+    }
+
+    if dry_run {
+        return Ok(());
+    }
+
+    if preview && !confirm("Apply the changes?") {
+        println!("Nothing was modified");
+        return Ok(());
+    }
+
+    save_captions(&changes, &writer)
+}
+
+fn tag_command(
+    path: &Path,
+    filter: Option<&Query>,
+    edit: &CaptionEdit,
+    format: &CaptionFormat,
+    preview: bool,
+    writer: CaptionWriter,
+    dry_run: bool,
+) -> Result<(), String> {
+    let dataset = Dataset::open(path).map_err(|e| format!("Failed to read directory: {}", e))?;
+    dataset.check_unique_names()?;
+    // Images without a caption are included, a tag can be added to them
+    let captions: Vec<(String, PathBuf, String)> = dataset
+        .entries()
+        .iter()
+        .map(|entry| {
+            let caption_path = entry.caption_path().to_path_buf();
+            (entry.name(), caption_path, entry.read_caption())
+        })
+        .filter(|(_, _, caption)| filter.is_none_or(|query| query.matches(caption, format)))
+        .collect();
+    if captions.is_empty() {
+        println!("No caption matches");
+        return Ok(());
+    }
+
+    let edits = edit.plan(
+        captions
+            .iter()
+            .map(|(name, _, caption)| (name.as_str(), caption.as_str())),
+    );
+    let mut changes = Vec::new();
+    for (file, (_, caption_path, _)) in edits.into_iter().zip(&captions) {
+        let caption_path = caption_path.clone();
+        match file.outcome {
+            EditOutcome::Changed(new_caption) => {
+                println!("{} {}", file.name.bold(), "modified".green());
+                if preview || dry_run {
+                    print_diff(&file.old, &new_caption);
+                    println!();
+                }
+                changes.push((caption_path, file.old, new_caption));
+            }
+            EditOutcome::Unchanged => println!("{} {}", file.name.bold(), "unchanged".dimmed()),
+            EditOutcome::Prose => {
+                println!("{} {}", file.name.bold(), "skipped, prose caption".yellow())
             }
-            start_tagging_gui(file_path.unwrap().to_str().unwrap());
-        } else {
-            let file_path = &args[2];
-            start_tagging_gui(file_path);
         }
-        return;
     }
 
-    if command == "sub" {
-        if args.len() < 4 {
-            println!("{}","Usage: sub <path> <string to replace> <string to replace with>\nThis command will modify the tags file".yellow());
-            return;
+    if changes.is_empty() {
+        println!("No caption was changed");
+        return Ok(());
+    }
+    if dry_run {
+        println!("{} files would be modified", changes.len());
+        return Ok(());
+    }
+    if preview && !confirm(&format!("Modify {} files?", changes.len())) {
+        println!("Nothing was modified");
+        return Ok(());
+    }
+
+    save_captions(&changes, &writer)
+}
+
+fn stats_command(
+    path: &Path,
+    top: usize,
+    format: StatsFormat,
+    caption_format: &CaptionFormat,
+) -> Result<(), String> {
+    let stats = DatasetStats::compute(path, caption_format, top);
+    match format {
+        StatsFormat::Table => print_stats(&stats, top),
+        StatsFormat::Json => {
+            let json = serde_json::to_string_pretty(&stats).map_err(|e| e.to_string())?;
+            println!("{}", json);
         }
-        let path = &args[2];
-        let old = &args[3];
-        let new = &args[4];
+        StatsFormat::Csv => {
+            println!("tag,count,share");
+            for tag in &stats.tags {
+                println!("{},{},{:.4}", csv_field(&tag.tag), tag.count, tag.share);
+            }
+        }
+    }
+    Ok(())
+}
 
-        let filter = |filename: &str| {
-            let ext = filename.split('.').last();
-            if ext.is_none() {
-                return false;
-            } else {
-                return ext.unwrap() == "txt";
+fn resize_command(
+    path: &Path,
+    output: &Path,
+    options: &ResizeOptions,
+    threads: usize,
+    dry_run: bool,
+) -> Result<(), String> {
+    let dataset = Dataset::open(path).map_err(|e| format!("Failed to read directory: {}", e))?;
+    dataset.check_unique_names()?;
+    if dataset.is_empty() {
+        return Err(format!("No images found in {}", path.display()));
+    }
+    let same_folder = fs::canonicalize(output)
+        .is_ok_and(|output| fs::canonicalize(path).is_ok_and(|path| path == output));
+    if same_folder {
+        return Err("The output folder must be different from the images folder".to_string());
+    }
+
+    if dry_run {
+        for entry in dataset.entries() {
+            let name = entry
+                .image_path()
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy();
+            match image::image_dimensions(entry.image_path()) {
+                Ok((width, height)) => {
+                    let (new_width, new_height) = options.output_size(width, height);
+                    println!(
+                        "{} {}x{} -> {}x{}",
+                        name.bold(),
+                        width,
+                        height,
+                        new_width,
+                        new_height
+                    );
+                }
+                Err(e) => println!("{} {}", name.bold(), e.to_string().red()),
             }
-        };
+        }
+        println!(
+            "{} images would be written to {}",
+            dataset.len(),
+            output.display()
+        );
+        return Ok(());
+    }
 
-        let files = get_files_in_folder(path, Some(&filter));
+    fs::create_dir_all(output)
+        .map_err(|e| format!("Failed to create {}: {}", output.display(), e))?;
+    let results = resize_all(dataset.entries(), output, options, threads);
+    let mut failed = 0;
+    for (entry, result) in dataset.entries().iter().zip(&results) {
+        let name = entry
+            .image_path()
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        match result {
+            Ok(Resized { from, to }) => println!(
+                "{} {}x{} -> {}x{}",
+                name.bold(),
+                from.0,
+                from.1,
+                to.0.to_string().green(),
+                to.1.to_string().green()
+            ),
+            Err(e) => {
+                failed += 1;
+                println!("{} {}", name.bold(), e.red());
+            }
+        }
+    }
 
-        substitute(&files, old, new);
-        return;
+    println!(
+        "{} images written to {}",
+        results.len() - failed,
+        output.display()
+    );
+    if failed > 0 {
+        return Err(format!("{} images could not be resized", failed));
     }
-    println!("Usage: rn <path> -> Renames all the files in the folder to the folder\n gui <path> -> Starts the GUI for tagging");
+    Ok(())
 }
 
-fn rename_file(path: &str, new_name: &str) {
-    for (cnt, file) in get_files_in_folder(path, None).into_iter().enumerate() {
-        let f_path = file.path().display().to_string();
-        let ext = if let Some(ext) = file.file_name().into_string().unwrap().split('.').last() {
-            ext.to_string()
-        } else {
-            String::new()
+fn buckets_command(
+    path: &Path,
+    options: &BucketOptions,
+    output: Option<&Path>,
+    filter: ResizeFilter,
+    threads: usize,
+    dry_run: bool,
+) -> Result<(), String> {
+    let dataset = Dataset::open(path).map_err(|e| format!("Failed to read directory: {}", e))?;
+    dataset.check_unique_names()?;
+    if dataset.is_empty() {
+        return Err(format!("No images found in {}", path.display()));
+    }
+    let entries = dataset.entries();
+    let sizes = run_parallel(entries, threads, |entry| {
+        image::image_dimensions(entry.image_path()).ok()
+    });
+    let plan = BucketPlan::new(&sizes, options);
+
+    let file_name = |entry: &DatasetEntry| {
+        let name = entry.image_path().file_name().unwrap_or_default();
+        name.to_string_lossy().to_string()
+    };
+    for ((entry, size), assignment) in entries.iter().zip(&sizes).zip(&plan.assignments) {
+        match (size, assignment) {
+            (Some((width, height)), Some(assignment)) => println!(
+                "{} {}x{} -> {}x{}, {:.1}% cropped",
+                file_name(entry).bold(),
+                width,
+                height,
+                assignment.bucket.0,
+                assignment.bucket.1,
+                assignment.crop_loss * 100.0
+            ),
+            _ => println!("{} {}", file_name(entry).bold(), "can't be read".red()),
+        }
+    }
+
+    println!("\n{}", "Buckets".bold());
+    for population in &plan.populations {
+        println!(
+            "  {:>11}  {:>6} images  {:>5.1}% cropped on average",
+            format!("{}x{}", population.bucket.0, population.bucket.1).cyan(),
+            population.images.len(),
+            population.mean_crop_loss * 100.0
+        );
+    }
+
+    let output = match output {
+        Some(output) => output,
+        None => return Ok(()),
+    };
+    let bucketed: Vec<(&DatasetEntry, (u32, u32))> = entries
+        .iter()
+        .zip(&plan.assignments)
+        .filter_map(|(entry, assignment)| assignment.map(|a| (entry, a.bucket)))
+        .collect();
+    if dry_run {
+        println!(
+            "\n{} images would be written to {}",
+            bucketed.len(),
+            output.display()
+        );
+        return Ok(());
+    }
+
+    let results = run_parallel(&bucketed, threads, |(entry, (width, height))| {
+        let dir = output.join(format!("{}x{}", width, height));
+        let options = ResizeOptions {
+            mode: ResizeMode::Fill,
+            width: *width,
+            height: *height,
+            filter,
+            pad_color: [0, 0, 0, 255],
         };
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))
+            .and_then(|_| resize_file(entry, &dir, &options))
+    });
+    let mut failed = 0;
+    for ((entry, _), result) in bucketed.iter().zip(&results) {
+        if let Err(e) = result {
+            failed += 1;
+            println!("{} {}", file_name(entry).bold(), e.red());
+        }
+    }
+
+    println!(
+        "\n{} images written to {}",
+        results.len() - failed,
+        output.display()
+    );
+    if failed > 0 {
+        return Err(format!("{} images could not be written", failed));
+    }
+    Ok(())
+}
+
+fn crop_command(
+    path: &Path,
+    originals: &Path,
+    output: &Path,
+    full_resolution: bool,
+    filter: ResizeFilter,
+    threads: usize,
+    dry_run: bool,
+) -> Result<(), String> {
+    let is_crop = |file: &Path| file.extension().is_some_and(|ext| ext == CROP_EXTENSION);
+    let mut crops: Vec<PathBuf> = get_files_in_folder(path, Some(&is_crop))
+        .into_iter()
+        .map(|file| file.path())
+        .collect();
+    if crops.is_empty() {
+        return Err(format!("No .crop files found in {}", path.display()));
+    }
+    crops.sort();
+    let same_folder = fs::canonicalize(output)
+        .is_ok_and(|output| fs::canonicalize(originals).is_ok_and(|originals| originals == output));
+    if same_folder {
+        return Err("The output folder must be different from the originals folder".to_string());
+    }
+
+    // The original with the name of the cropped image, or with the same stem and another extension
+    let find_original = |crop: &Path, record: &CropRecord| {
+        let stem = crop.file_stem().unwrap_or_default().to_string_lossy();
+        let found = std::iter::once(originals.join(&record.image))
+            .chain(
+                IMAGE_EXTENSIONS
+                    .iter()
+                    .map(|ext| originals.join(format!("{}.{}", stem, ext))),
+            )
+            .find(|original| original.is_file());
+        found.ok_or_else(|| format!("No original image found for {}", record.image))
+    };
+    let jobs: Vec<_> = crops
+        .into_iter()
+        .map(|crop| {
+            let job = CropRecord::load(&crop).and_then(|mut record| {
+                if full_resolution {
+                    record.size = None;
+                }
+                find_original(&crop, &record).map(|original| (record, original))
+            });
+            (crop, job)
+        })
+        .collect();
+
+    let file_name = |path: &Path| {
+        let name = path.file_name().unwrap_or_default();
+        name.to_string_lossy().to_string()
+    };
+    if dry_run {
+        for (crop, job) in &jobs {
+            match job {
+                Ok((record, original)) => println!(
+                    "{} {}x{} at {},{} of {}",
+                    file_name(original).bold(),
+                    record.rect.width,
+                    record.rect.height,
+                    record.rect.x,
+                    record.rect.y,
+                    file_name(crop)
+                ),
+                Err(e) => println!("{} {}", file_name(crop).bold(), e.red()),
+            }
+        }
+        println!(
+            "{} images would be written to {}",
+            jobs.iter().filter(|(_, job)| job.is_ok()).count(),
+            output.display()
+        );
+        return Ok(());
+    }
+
+    fs::create_dir_all(output)
+        .map_err(|e| format!("Failed to create {}: {}", output.display(), e))?;
+    let results = run_parallel(&jobs, threads, |(crop, job)| {
+        let (record, original) = job.as_ref().map_err(String::clone)?;
+        let out_path = output.join(original.file_name().unwrap_or_default());
+        let record = crop_file(original, &out_path, record, filter)?;
+        // The caption is the one of the cropped image, it could have been edited since
+        let caption = caption_path(crop);
+        if caption.is_file() {
+            fs::copy(&caption, caption_path(&out_path))
+                .map_err(|e| format!("Failed to copy the caption: {}", e))?;
+        }
+        Ok::<_, String>((record, out_path))
+    });
+
+    let mut failed = 0;
+    for ((crop, _), result) in jobs.iter().zip(&results) {
+        match result {
+            Ok((record, out_path)) => {
+                let size = record.cropped_size();
+                println!(
+                    "{} {}x{} -> {}x{}",
+                    file_name(out_path).bold(),
+                    record.original.0,
+                    record.original.1,
+                    size.0.to_string().green(),
+                    size.1.to_string().green()
+                );
+            }
+            Err(e) => {
+                failed += 1;
+                println!("{} {}", file_name(crop).bold(), e.red());
+            }
+        }
+    }
+
+    println!(
+        "{} images written to {}",
+        results.len() - failed,
+        output.display()
+    );
+    if failed > 0 {
+        return Err(format!("{} images could not be cropped", failed));
+    }
+    Ok(())
+}
+
+// One thread per core when the number is not given
+fn threads_or_cores(threads: Option<usize>) -> usize {
+    threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+}
+
+fn print_stats(stats: &DatasetStats, top: usize) {
+    println!(
+        "{} images, {} captions ({} prose), {} different tags\n",
+        stats.images,
+        stats.captions,
+        stats.prose_captions,
+        stats.tags.len()
+    );
+
+    println!("{}", "Most used tags".bold());
+    let width = stats
+        .tags
+        .iter()
+        .take(top)
+        .map(|t| t.tag.chars().count())
+        .max()
+        .unwrap_or(0);
+    for tag in stats.tags.iter().take(top) {
+        println!(
+            "  {:<width$}  {:>6}  {:>6.1}%",
+            tag.tag.cyan(),
+            tag.count,
+            tag.share * 100.0,
+            width = width
+        );
+    }
+
+    if !stats.co_occurrences.is_empty() {
+        println!("\n{}", "Tags used together".bold());
+        for pair in &stats.co_occurrences {
+            println!(
+                "  {:>6}  {} + {}",
+                pair.count,
+                pair.first.cyan(),
+                pair.second.cyan()
+            );
+        }
+    }
 
-        let new_path = path.to_string() + "\\" + new_name + " (" + &cnt.to_string() + ")." + &ext;
+    let lengths = &stats.caption_lengths;
+    if !lengths.histogram.is_empty() {
+        println!("\n{}", "Tags per caption".bold());
+        println!(
+            "  min {}  max {}  median {}  mean {:.1}  mean characters {:.0}",
+            lengths.min_tags,
+            lengths.max_tags,
+            lengths.median_tags,
+            lengths.mean_tags,
+            lengths.mean_chars
+        );
+        let max_count = lengths
+            .histogram
+            .iter()
+            .map(|b| b.count)
+            .max()
+            .unwrap_or(1)
+            .max(1);
+        for bucket in &lengths.histogram {
+            let bar = "#".repeat(bucket.count * 40 / max_count);
+            println!(
+                "  {:>4}-{:<4} {:>6} {}",
+                bucket.from,
+                bucket.to,
+                bucket.count,
+                bar.green()
+            );
+        }
+    }
+
+    if !stats.images_without_captions.is_empty() {
+        println!("\n{}", "Images without captions".yellow().bold());
+        for image in &stats.images_without_captions {
+            println!("  {}", image.display());
+        }
+    }
 
-        println!("OLD: {}\nNEW: {}\n", f_path.red(), new_path.green());
-        if fs::rename(f_path, new_path).is_err() {
-            println!("Failde to execute previous rename");
+    if !stats.captions_without_images.is_empty() {
+        println!("\n{}", "Captions without images".yellow().bold());
+        for caption in &stats.captions_without_images {
+            println!("  {}", caption.display());
         }
     }
 }
 
-fn start_tagging_gui(path: &str) {
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Paths given on the command line may use either separator, regardless of the OS we are running on
+// On Windows both separators already work. Elsewhere `\` is a valid character of a file name,
+// so it is read as a separator only when the path does not exist as written
+fn arg_to_path(arg: &str) -> PathBuf {
+    let path = PathBuf::from(arg);
+    if MAIN_SEPARATOR == '\\' || !arg.contains('\\') || path.exists() {
+        path
+    } else {
+        PathBuf::from(arg.replace('\\', "/"))
+    }
+}
+
+fn parse_path(arg: &str) -> Result<PathBuf, String> {
+    Ok(arg_to_path(arg))
+}
+
+fn parse_dir(arg: &str) -> Result<PathBuf, String> {
+    let path = arg_to_path(arg);
+    if !path.is_dir() {
+        return Err(format!("{} is not a directory", path.display()));
+    }
+    Ok(path)
+}
+
+fn parse_seconds(arg: &str) -> Result<f64, String> {
+    let seconds: f64 = arg
+        .parse()
+        .map_err(|_| format!("{} is not a number of seconds", arg))?;
+    if !seconds.is_finite() || seconds <= 0.0 {
+        return Err("the delay must be a finite number of seconds above 0".to_string());
+    }
+    Ok(seconds)
+}
+
+// The name of the folder, "." and ".." are resolved to the real folder name
+fn dir_name(path: &Path) -> Option<String> {
+    let name = match path.file_name() {
+        Some(name) => name.to_os_string(),
+        None => fs::canonicalize(path).ok()?.file_name()?.to_os_string(),
+    };
+    name.into_string().ok()
+}
+
+fn start_tagging_gui(
+    path: &Path,
+    tag_list: Option<&Path>,
+    shortcuts: Option<&Path>,
+    gui_options: GuiOptions,
+) -> Result<(), String> {
     // env_logger::init();
 
+    let dataset = Dataset::open(path).map_err(|e| format!("Failed to read directory: {}", e))?;
+    dataset.check_unique_names()?;
+    if dataset.is_empty() {
+        return Err(format!("No images found in {}", path.display()));
+    }
+
+    let mut vocabulary = Vocabulary::from_dataset(&dataset, &gui_options.caption_format);
+    if let Some(tag_list) = tag_list {
+        let imported = vocabulary
+            .import_csv(tag_list)
+            .map_err(|e| format!("Failed to read {}: {}", tag_list.display(), e))?;
+        println!("Imported {} tags", imported);
+    }
+
+    let shortcuts = match shortcuts {
+        Some(shortcuts) => Shortcuts::load(shortcuts)
+            .map_err(|e| format!("Failed to read {}: {}", shortcuts.display(), e))?,
+        None => Shortcuts::default(),
+    };
+
     let options = eframe::NativeOptions {
         initial_window_size: Some(egui::vec2(840.0, 720.0)),
         ..Default::default()
     };
 
-    let gui = tag_gui::TagGui::new(path);
+    let gui = tag_gui::TagGui::new(dataset, vocabulary, shortcuts, gui_options);
     eframe::run_native(
-        "Dataset images tagging util",
+        tag_gui::WINDOW_TITLE,
         options,
         Box::new(|_cc| Box::<tag_gui::TagGui>::new(gui)),
     )
-    .unwrap();
+    .map_err(|e| format!("Failed to start the gui: {}", e))
 }
 
-fn get_files_in_folder(path: &str, filter: Option<&dyn Fn(&str) -> bool>) -> Vec<DirEntry> {
-    let files = fs::read_dir(path);
-    if files.is_err() {
-        println!("Failed to read directory");
-        return Vec::new();
-    }
-    let files = files.unwrap();
-    let mut good_files = Vec::new();
+// The files whose caption is changed by the substitution, with the old and the new caption
+fn plan_substitution(
+    files: &[PathBuf],
+    substitution: &Substitution,
+) -> Vec<(PathBuf, String, String)> {
+    let mut changes = Vec::new();
+    for file in files {
+        let caption = fs::read_to_string(file);
+        if caption.is_err() {
+            continue;
+        }
+        let caption = caption.unwrap();
 
-    if let Some(filter) = filter {
-        for file in files {
-            if let Ok(file) = file {
-                if filter(file.path().to_str().unwrap()) {
-                    good_files.push(file);
-                }
-            }
+        let new_caption = substitution.apply(&caption);
+        if new_caption != caption {
+            changes.push((file.clone(), caption, new_caption));
         }
-    } else {
-        for file in files {
-            if let Ok(file) = file {
-                good_files.push(file);
-            }
+    }
+    changes
+}
+
+fn save_captions(
+    changes: &[(PathBuf, String, String)],
+    writer: &CaptionWriter,
+) -> Result<(), String> {
+    let mut failed = 0;
+    for (file, _, new_caption) in changes {
+        if let Err(e) = writer.write(file, new_caption) {
+            println!("Error saving file {}: {}", file.display(), e);
+            failed += 1;
         }
     }
 
-    // if we do not save the state of the directory, the same file will be renamed multiple times
-    good_files
+    if failed > 0 {
+        return Err(format!("{} caption files could not be saved", failed));
+    }
+    println!("Modified {} files", changes.len());
+    Ok(())
 }
 
-fn substitute(files: &Vec<DirEntry>, old: &str, new: &str) {
-    for file in files {
-        let caption = fs::read_to_string(file.path());
-        if caption.is_err() {
+// Prints the changed lines, only the part of the line that differs is colored
+fn print_diff(old: &str, new: &str) {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    for i in 0..old_lines.len().max(new_lines.len()) {
+        let old_line = old_lines.get(i).copied().unwrap_or_default();
+        let new_line = new_lines.get(i).copied().unwrap_or_default();
+        if old_line == new_line {
             continue;
         }
-        let caption = caption.unwrap();
 
-        let new_caption = caption.replace(old, new);
+        let prefix = old_line
+            .char_indices()
+            .zip(new_line.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map_or(0, |((i, c), _)| i + c.len_utf8());
+        let suffix = old_line[prefix..]
+            .chars()
+            .rev()
+            .zip(new_line[prefix..].chars().rev())
+            .take_while(|(a, b)| a == b)
+            .map(|(c, _)| c.len_utf8())
+            .sum::<usize>();
 
-        let file = File::create(file.path());
-        if file.is_err() {
-            println!("Impossible to save");
-            continue;
+        let old_end = old_line.len() - suffix;
+        let new_end = new_line.len() - suffix;
+        println!(
+            "{} {}{}{}",
+            "-".red(),
+            &old_line[..prefix],
+            old_line[prefix..old_end].red().bold(),
+            &old_line[old_end..]
+        );
+        println!(
+            "{} {}{}{}",
+            "+".green(),
+            &new_line[..prefix],
+            new_line[prefix..new_end].green().bold(),
+            &new_line[new_end..]
+        );
+    }
+}
+
+fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    if io::stdout().flush().is_err() {
+        return false;
+    }
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ai_utils::caption::Caption;
+    use ai_utils::rename::{self, Rename};
+
+    // Empty folder in the temp dir, removed when the test starts again
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ai_utils_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // The same path written with backslashes
+    fn backslashes(path: &Path) -> String {
+        path.to_string_lossy().replace('/', "\\")
+    }
+
+    #[test]
+    fn arg_to_path_accepts_both_separators() {
+        let dir = test_dir("arg_to_path").join("images");
+        fs::create_dir_all(&dir).unwrap();
+
+        assert_eq!(arg_to_path(&dir.to_string_lossy()), dir);
+        assert_eq!(arg_to_path(&backslashes(&dir)), dir);
+        assert!(parse_dir(&backslashes(&dir)).is_ok());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn arg_to_path_keeps_existing_backslashes() {
+        let dir = test_dir("backslash");
+        let file = dir.join("a\\b.txt");
+        fs::write(&file, "").unwrap();
+
+        let arg = file.to_string_lossy().to_string();
+        assert_eq!(arg_to_path(&arg), file);
+        assert_eq!(
+            arg_to_path("missing\\b.txt"),
+            PathBuf::from("missing/b.txt")
+        );
+    }
+
+    #[test]
+    fn dir_name_with_both_separators() {
+        let dir = test_dir("dir_name").join("my set");
+        fs::create_dir_all(&dir).unwrap();
+
+        for arg in [dir.to_string_lossy().to_string(), backslashes(&dir)] {
+            assert_eq!(dir_name(&arg_to_path(&arg)).as_deref(), Some("my set"));
         }
+        let dot = arg_to_path(&format!("{}/.", dir.display()));
+        assert_eq!(dir_name(&dot).as_deref(), Some("my set"));
+    }
+
+    #[test]
+    fn parse_seconds_rejects_non_finite_and_non_positive() {
+        assert_eq!(parse_seconds("2.5"), Ok(2.5));
+        for arg in ["inf", "nan", "-1", "0", "soon"] {
+            assert!(parse_seconds(arg).is_err(), "{}", arg);
+        }
+    }
 
-        if write!(file.unwrap(), "{}", new_caption).is_err() {
-            println!("Error saving file");
+    #[test]
+    fn images_with_the_same_name_share_one_caption() {
+        let dir = test_dir("same_name");
+        for file in ["cat.png", "cat.jpg", "cat.txt"] {
+            fs::write(dir.join(file), "").unwrap();
         }
+
+        let dataset = Dataset::open(&dir).unwrap();
+        assert_eq!(dataset.caption_files(), [dir.join("cat.txt")]);
+        assert!(dataset.check_unique_names().is_err());
+    }
+
+    #[test]
+    fn caption_keeps_the_weight_text() {
+        let format = CaptionFormat::default();
+        let text = "(cat:1.0), (dog:1.10), (bird: 0.5 ), fish";
+        let caption = Caption::parse(text, &format);
+        assert_eq!(caption.tags()[1].weight.as_ref().unwrap().value, 1.1);
+        assert_eq!(
+            caption.serialize(&format),
+            "(cat:1.0), (dog:1.10), (bird:0.5), fish"
+        );
+    }
+
+    #[test]
+    fn rn_targets_with_both_separators() {
+        let dir = test_dir("rn_targets").join("cats");
+        fs::create_dir_all(&dir).unwrap();
+        let dir = fs::canonicalize(&dir).unwrap();
+        fs::write(dir.join("b.png"), "").unwrap();
+        fs::write(dir.join("b.txt"), "").unwrap();
+
+        for arg in [dir.to_string_lossy().to_string(), backslashes(&dir)] {
+            let path = arg_to_path(&arg);
+            let options = RenameOptions::new(&dir_name(&path).unwrap());
+            let plan = RenamePlan::for_folder(&path, &options).unwrap();
+            let targets: Vec<&Path> = plan.renames().iter().map(|r| r.to.as_path()).collect();
+            assert_eq!(
+                targets,
+                [dir.join("cats (0).png"), dir.join("cats (0).txt")]
+            );
+        }
+    }
+
+    #[test]
+    fn rn_keeps_backups_with_their_image() {
+        let dir = test_dir("rn_backups").join("cats");
+        fs::create_dir_all(&dir).unwrap();
+        let dir = fs::canonicalize(&dir).unwrap();
+        for file in ["a.png", "a.txt", "a.txt.bak", "b.png", "b.txt"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        let plan = RenamePlan::for_folder(&dir, &RenameOptions::new("cats")).unwrap();
+        let mut targets: Vec<PathBuf> = plan.renames().iter().map(|r| r.to.clone()).collect();
+        targets.sort();
+        let expected: Vec<PathBuf> = [
+            "cats (0).png",
+            "cats (0).txt",
+            "cats (0).txt.bak",
+            "cats (1).png",
+            "cats (1).txt",
+        ]
+        .iter()
+        .map(|name| dir.join(name))
+        .collect();
+        assert_eq!(targets, expected);
+    }
+
+    // Name and content of every file in the folder
+    fn folder_files(dir: &Path) -> Vec<(String, String)> {
+        let mut files: Vec<(String, String)> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| !rename::is_journal(path))
+            .map(|path| {
+                let name = path.file_name().unwrap().to_string_lossy().to_string();
+                (name, fs::read_to_string(&path).unwrap())
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn rn_round_trip_through_the_journal() {
+        let dir = test_dir("rn_round_trip").join("cats");
+        fs::create_dir_all(&dir).unwrap();
+        for file in ["b.png", "b.txt", "a.png", "a.txt"] {
+            fs::write(dir.join(file), file).unwrap();
+        }
+        let before = folder_files(&dir);
+
+        // The "." is resolved, the journal must not depend on how the folder was given
+        let plan = RenamePlan::for_folder(&dir.join("."), &RenameOptions::new("cats")).unwrap();
+        assert!(plan.renames().iter().all(|r| r.from.is_absolute()));
+        assert!(plan.collisions().is_empty());
+        let (journal, result) = plan.execute(false);
+        result.unwrap();
+        let journal_path = Journal::default_path(&dir);
+        journal.save(&journal_path).unwrap();
+        assert_eq!(
+            folder_files(&dir),
+            [
+                ("cats (0).png".to_string(), "a.png".to_string()),
+                ("cats (0).txt".to_string(), "a.txt".to_string()),
+                ("cats (1).png".to_string(), "b.png".to_string()),
+                ("cats (1).txt".to_string(), "b.txt".to_string()),
+            ]
+        );
+
+        let (_, result) = Journal::load(&journal_path)
+            .unwrap()
+            .undo_plan()
+            .execute(true);
+        result.unwrap();
+        assert_eq!(folder_files(&dir), before);
+    }
+
+    #[test]
+    fn rn_swaps_names_only_in_two_phases() {
+        let dir = test_dir("rn_swap");
+        fs::write(dir.join("a.png"), "a").unwrap();
+        fs::write(dir.join("b.png"), "b").unwrap();
+        let plan = RenamePlan::new(vec![
+            Rename {
+                from: dir.join("a.png"),
+                to: dir.join("b.png"),
+            },
+            Rename {
+                from: dir.join("b.png"),
+                to: dir.join("a.png"),
+            },
+        ]);
+        assert!(plan.collisions().is_empty());
+        assert!(plan.needs_two_phase());
+
+        let (journal, result) = plan.execute(false);
+        assert!(result.is_err());
+        assert!(journal.renames.is_empty());
+        assert_eq!(fs::read_to_string(dir.join("a.png")).unwrap(), "a");
+
+        let (journal, result) = plan.execute(true);
+        result.unwrap();
+        assert_eq!(journal.renames.len(), 2);
+        assert_eq!(fs::read_to_string(dir.join("a.png")).unwrap(), "b");
+
+        let (_, result) = journal.undo_plan().execute(true);
+        result.unwrap();
+        assert_eq!(fs::read_to_string(dir.join("a.png")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(dir.join("b.png")).unwrap(), "b");
+    }
+
+    #[test]
+    fn rn_refuses_to_overwrite_files() {
+        let dir = test_dir("rn_collisions");
+        for file in ["a.png", "b.png", "c.png"] {
+            fs::write(dir.join(file), file).unwrap();
+        }
+        let rename = |from: &str, to: &str| Rename {
+            from: dir.join(from),
+            to: dir.join(to),
+        };
+
+        // c.png is not renamed, so it would be lost
+        let plan = RenamePlan::new(vec![rename("a.png", "c.png")]);
+        assert_eq!(plan.collisions(), [dir.join("c.png")]);
+        // Two files to the same name
+        let plan = RenamePlan::new(vec![rename("a.png", "d.png"), rename("b.png", "d.png")]);
+        assert_eq!(plan.collisions(), [dir.join("d.png")]);
+
+        for two_phase in [false, true] {
+            let (journal, result) = plan.execute(two_phase);
+            assert!(result.is_err());
+            assert!(journal.renames.is_empty());
+        }
+        assert!(!dir.join("d.png").exists());
+        assert_eq!(fs::read_to_string(dir.join("a.png")).unwrap(), "a.png");
+    }
+
+    #[test]
+    fn rn_journals_of_the_same_second_are_kept() {
+        let dir = test_dir("rn_journals");
+        let first = Journal::default_path(&dir);
+        Journal::default().save(&first).unwrap();
+        let second = Journal::default_path(&dir);
+        assert_ne!(first, second);
+        assert!(rename::is_journal(&second));
     }
 }
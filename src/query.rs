@@ -0,0 +1,231 @@
+use regex::Regex;
+use std::fmt;
+
+use crate::caption::{Caption, CaptionFormat};
+
+// A search over the captions, for example `blue eyes AND NOT (hat OR /smil(e|ing)/)`.
+// Consecutive words are a single tag, quotes are needed only for tags containing an operator,
+// `/.../` is a regex on the whole caption text and `:empty` matches the empty captions.
+// Two terms without an operator between them must both match
+#[derive(Clone, Debug)]
+pub enum Query {
+    Tag(String),
+    Regex(Regex),
+    Empty,
+    Not(Box<Query>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Empty,
+    Word(String),
+    Quoted(String),
+    Regex(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+            Token::And => write!(f, "AND"),
+            Token::Or => write!(f, "OR"),
+            Token::Not => write!(f, "NOT"),
+            Token::Empty => write!(f, ":empty"),
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Quoted(tag) => write!(f, "\"{}\"", tag),
+            Token::Regex(pattern) => write!(f, "/{}/", pattern),
+        }
+    }
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let tokens = tokenize(text)?;
+        if tokens.is_empty() {
+            return Err("The query is empty".to_string());
+        }
+
+        let mut parser = Parser { tokens, pos: 0 };
+        let query = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(query),
+            Some(Token::Close) => Err("Unmatched )".to_string()),
+            Some(token) => Err(format!("Unexpected {}", token)),
+        }
+    }
+
+    pub fn matches(&self, caption: &str, format: &CaptionFormat) -> bool {
+        self.eval(caption, &Caption::parse(caption, format))
+    }
+
+    fn eval(&self, text: &str, caption: &Caption) -> bool {
+        match self {
+            // Prose captions have no tags, only a regex can find something in them
+            Query::Tag(name) => caption
+                .tags()
+                .iter()
+                .any(|tag| tag.name.to_lowercase() == *name),
+            Query::Regex(regex) => regex.is_match(text),
+            Query::Empty => text.trim().is_empty(),
+            Query::Not(query) => !query.eval(text, caption),
+            Query::And(queries) => queries.iter().all(|query| query.eval(text, caption)),
+            Query::Or(queries) => queries.iter().any(|query| query.eval(text, caption)),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '"' => {
+                let mut tag = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => tag.push(c),
+                        None => return Err("Missing closing \"".to_string()),
+                    }
+                }
+                tokens.push(Token::Quoted(tag));
+            }
+            '/' => {
+                let mut pattern = String::new();
+                loop {
+                    match chars.next() {
+                        Some('/') => break,
+                        // "\/" is a slash inside the regex
+                        Some('\\') if chars.peek() == Some(&'/') => {
+                            pattern.push('/');
+                            chars.next();
+                        }
+                        Some(c) => pattern.push(c),
+                        None => return Err("Missing closing / of the regex".to_string()),
+                    }
+                }
+                tokens.push(Token::Regex(pattern));
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.peek() {
+                    if c.is_whitespace() || *c == '(' || *c == ')' {
+                        break;
+                    }
+                    word.push(*c);
+                    chars.next();
+                }
+                tokens.push(match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    ":empty" => Token::Empty,
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Query, String> {
+        let mut queries = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            queries.push(self.and()?);
+        }
+        Ok(if queries.len() == 1 {
+            queries.remove(0)
+        } else {
+            Query::Or(queries)
+        })
+    }
+
+    fn and(&mut self) -> Result<Query, String> {
+        let mut queries = vec![self.not()?];
+        loop {
+            match self.peek() {
+                None | Some(Token::Or) | Some(Token::Close) => break,
+                Some(Token::And) => self.pos += 1,
+                Some(_) => {}
+            }
+            queries.push(self.not()?);
+        }
+        Ok(if queries.len() == 1 {
+            queries.remove(0)
+        } else {
+            Query::And(queries)
+        })
+    }
+
+    fn not(&mut self) -> Result<Query, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Query::Not(Box::new(self.not()?)));
+        }
+        self.term()
+    }
+
+    fn term(&mut self) -> Result<Query, String> {
+        match self.next() {
+            Some(Token::Open) => {
+                let query = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(query),
+                    _ => Err("Missing )".to_string()),
+                }
+            }
+            Some(Token::Empty) => Ok(Query::Empty),
+            Some(Token::Quoted(tag)) => Ok(Query::Tag(normalize(&tag))),
+            Some(Token::Regex(pattern)) => Regex::new(&pattern)
+                .map(Query::Regex)
+                .map_err(|e| format!("Invalid regex: {}", e)),
+            Some(Token::Word(word)) => {
+                let mut tag = word;
+                while let Some(Token::Word(word)) = self.peek() {
+                    tag.push(' ');
+                    tag.push_str(word);
+                    self.pos += 1;
+                }
+                Ok(Query::Tag(normalize(&tag)))
+            }
+            Some(token) => Err(format!("Unexpected {}", token)),
+            None => Err("The query ends too early".to_string()),
+        }
+    }
+}
+
+// Tags are compared ignoring the case and the extra whitespace
+fn normalize(tag: &str) -> String {
+    tag.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
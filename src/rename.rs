@@ -0,0 +1,367 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::caption_writer::BACKUP_EXTENSION;
+use crate::dataset::get_files_in_folder;
+
+// Journals are written inside the renamed folder, so they must never be renamed themselves
+pub const JOURNAL_PREFIX: &str = ".rn_journal_";
+const TMP_PREFIX: &str = ".rn_tmp_";
+
+pub fn is_journal(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with(JOURNAL_PREFIX))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RenameOrder {
+    // Plain string ordering of the file names
+    Name,
+    // Numbers inside the names are compared by value, so "img2" comes before "img10"
+    Natural,
+    // Oldest files first
+    Modified,
+}
+
+#[derive(Clone, Debug)]
+pub struct RenameOptions {
+    // "{name}" is replaced by the given name and "{n}" by the counter
+    pub template: String,
+    pub name: String,
+    // Minimum number of digits of the counter, padded with zeros
+    pub pad: usize,
+    pub start: usize,
+    pub order: RenameOrder,
+}
+
+impl RenameOptions {
+    pub fn new(name: &str) -> Self {
+        Self {
+            template: "{name} ({n})".to_string(),
+            name: name.to_string(),
+            pad: 0,
+            start: 0,
+            order: RenameOrder::Natural,
+        }
+    }
+
+    fn new_stem(&self, cnt: usize) -> String {
+        let n = format!("{:0width$}", self.start + cnt, width = self.pad);
+        self.template
+            .replace("{name}", &self.name)
+            .replace("{n}", &n)
+    }
+}
+
+// The stem the file is grouped by and the rest of its name, which is kept by the rename.
+// A backup is grouped with the file it copies, "cat.txt.bak" goes with "cat.png"
+fn split_name(file: &Path) -> (String, String) {
+    let name = file
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let mut base = Path::new(&name);
+    if base.extension().is_some_and(|ext| ext == BACKUP_EXTENSION) {
+        base = Path::new(base.file_stem().unwrap_or_default());
+    }
+    let stem = base.file_stem().unwrap_or_default().to_string_lossy();
+    let suffix = name[stem.len()..].to_string();
+    (stem.to_string(), suffix)
+}
+
+// Files that share the same stem and are renamed together
+struct FileGroup {
+    stem: String,
+    files: Vec<PathBuf>,
+}
+
+impl FileGroup {
+    // The group is as old as its oldest file
+    fn modified(&self) -> Option<SystemTime> {
+        self.files
+            .iter()
+            .filter_map(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+            .min()
+    }
+}
+
+// Compares strings treating runs of digits as numbers
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                // Compare by length first, so numbers of any size work without overflowing
+                let x_trim = x.trim_start_matches('0');
+                let y_trim = y.trim_start_matches('0');
+                let ord = x_trim
+                    .len()
+                    .cmp(&y_trim.len())
+                    .then_with(|| x_trim.cmp(y_trim))
+                    .then_with(|| x.len().cmp(&y.len()));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut number = String::new();
+    while let Some(c) = chars.peek().copied() {
+        if !c.is_ascii_digit() {
+            break;
+        }
+        number.push(c);
+        chars.next();
+    }
+    number
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+// Every rename that will be executed, computed before touching the file system
+#[derive(Clone, Debug, Default)]
+pub struct RenamePlan {
+    renames: Vec<Rename>,
+}
+
+impl RenamePlan {
+    pub fn new(renames: Vec<Rename>) -> Self {
+        // Renaming a file to itself is a no op
+        let renames = renames.into_iter().filter(|r| r.from != r.to).collect();
+        Self { renames }
+    }
+
+    // Renames all the files in the folder following the template, files sharing the same stem
+    // (the image, its caption and any other sidecar) keep sharing it after the rename.
+    // The paths are absolute, so the journal can be undone from any folder
+    pub fn for_folder(dir: &Path, options: &RenameOptions) -> Result<Self, String> {
+        if !options.template.contains("{n}") {
+            return Err("The name template must contain {n}".to_string());
+        }
+        let dir = &fs::canonicalize(dir)
+            .map_err(|e| format!("Failed to open {}: {}", dir.display(), e))?;
+
+        let filter = |file: &Path| file.is_file() && !is_journal(file);
+        let files: Vec<PathBuf> = get_files_in_folder(dir, Some(&filter))
+            .into_iter()
+            .map(|file| file.path())
+            .collect();
+
+        let mut groups: Vec<FileGroup> = Vec::new();
+        for file in files {
+            let (stem, _) = split_name(&file);
+            match groups.iter_mut().find(|group| group.stem == stem) {
+                Some(group) => group.files.push(file),
+                None => groups.push(FileGroup {
+                    stem,
+                    files: vec![file],
+                }),
+            }
+        }
+
+        match options.order {
+            RenameOrder::Name => groups.sort_by(|a, b| a.stem.cmp(&b.stem)),
+            RenameOrder::Natural => groups.sort_by(|a, b| natural_cmp(&a.stem, &b.stem)),
+            RenameOrder::Modified => groups.sort_by(|a, b| {
+                a.modified()
+                    .cmp(&b.modified())
+                    .then_with(|| natural_cmp(&a.stem, &b.stem))
+            }),
+        }
+
+        let mut renames = Vec::new();
+        for (cnt, group) in groups.iter_mut().enumerate() {
+            let new_stem = options.new_stem(cnt);
+            group.files.sort();
+            for from in &group.files {
+                let (_, suffix) = split_name(from);
+                renames.push(Rename {
+                    to: dir.join(format!("{}{}", new_stem, suffix)),
+                    from: from.clone(),
+                });
+            }
+        }
+
+        Ok(RenamePlan::new(renames))
+    }
+
+    pub fn renames(&self) -> &[Rename] {
+        &self.renames
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.renames.is_empty()
+    }
+
+    // Targets that would overwrite a file that is not part of the plan, or that are used twice
+    pub fn collisions(&self) -> Vec<PathBuf> {
+        let sources: HashSet<&PathBuf> = self.renames.iter().map(|r| &r.from).collect();
+        let mut targets = HashSet::new();
+        let mut collisions = Vec::new();
+        for rename in &self.renames {
+            let duplicated = !targets.insert(&rename.to);
+            let overwrites = rename.to.exists() && !sources.contains(&rename.to);
+            if duplicated || overwrites {
+                collisions.push(rename.to.clone());
+            }
+        }
+        collisions
+    }
+
+    // True when a target is the source of another rename, so renaming in order would overwrite it
+    pub fn needs_two_phase(&self) -> bool {
+        let sources: HashSet<&PathBuf> = self.renames.iter().map(|r| &r.from).collect();
+        self.renames.iter().any(|r| sources.contains(&r.to))
+    }
+
+    // Executes the plan, the returned journal contains the renames that were completed
+    // and can be used to undo them even if the execution stopped halfway
+    pub fn execute(&self, two_phase: bool) -> (Journal, Result<(), String>) {
+        let mut journal = Journal::default();
+
+        let collisions = self.collisions();
+        if !collisions.is_empty() {
+            return (
+                journal,
+                Err(format!(
+                    "{} renames would overwrite existing files, first one: {}",
+                    collisions.len(),
+                    collisions[0].display()
+                )),
+            );
+        }
+
+        if !two_phase {
+            if self.needs_two_phase() {
+                return (
+                    journal,
+                    Err("Some new names are already used by files of the folder, use two phase renaming".to_string()),
+                );
+            }
+            for rename in &self.renames {
+                if let Err(e) = fs::rename(&rename.from, &rename.to) {
+                    let msg = format!("Failed to rename {}: {}", rename.from.display(), e);
+                    return (journal, Err(msg));
+                }
+                journal.renames.push(rename.clone());
+            }
+            return (journal, Ok(()));
+        }
+
+        // First every file is moved to a temporary name, so no target can be occupied by a file yet to move
+        let mut moved: Vec<(&Rename, PathBuf)> = Vec::new();
+        for (cnt, rename) in self.renames.iter().enumerate() {
+            let tmp = tmp_path(&rename.from, cnt);
+            if let Err(e) = fs::rename(&rename.from, &tmp) {
+                let msg = format!("Failed to rename {}: {}", rename.from.display(), e);
+                // Nothing has reached its final name yet, so we put everything back
+                for (rename, tmp) in moved.iter().rev() {
+                    let _ = fs::rename(tmp, &rename.from);
+                }
+                return (journal, Err(msg));
+            }
+            moved.push((rename, tmp));
+        }
+
+        let mut result = Ok(());
+        for (rename, tmp) in moved {
+            if result.is_err() {
+                let _ = fs::rename(&tmp, &rename.from);
+                continue;
+            }
+            match fs::rename(&tmp, &rename.to) {
+                Ok(()) => journal.renames.push(rename.clone()),
+                Err(e) => {
+                    let _ = fs::rename(&tmp, &rename.from);
+                    result = Err(format!("Failed to rename {}: {}", rename.from.display(), e));
+                }
+            }
+        }
+        (journal, result)
+    }
+}
+
+fn tmp_path(path: &Path, cnt: usize) -> PathBuf {
+    let name = format!("{}{}_{}", TMP_PREFIX, std::process::id(), cnt);
+    match path.parent() {
+        Some(parent) => parent.join(name),
+        None => PathBuf::from(name),
+    }
+}
+
+// Record of the executed renames, saved as json so that `rn --undo` can restore the original names
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Journal {
+    pub renames: Vec<Rename>,
+}
+
+impl Journal {
+    // A new file every time, two runs in the same second get a counter after the time
+    pub fn default_path(dir: &Path) -> PathBuf {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+        let mut path = dir.join(format!("{}{}.json", JOURNAL_PREFIX, secs));
+        let mut cnt = 1;
+        while path.exists() {
+            path = dir.join(format!("{}{}_{}.json", JOURNAL_PREFIX, secs, cnt));
+            cnt += 1;
+        }
+        path
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read journal {}: {}", path.display(), e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid journal {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, content)
+            .map_err(|e| format!("Failed to write journal {}: {}", path.display(), e))
+    }
+
+    // The plan that brings every file back to its original name
+    pub fn undo_plan(&self) -> RenamePlan {
+        let renames = self
+            .renames
+            .iter()
+            .rev()
+            .map(|r| Rename {
+                from: r.to.clone(),
+                to: r.from.clone(),
+            })
+            .collect();
+        RenamePlan::new(renames)
+    }
+}
@@ -0,0 +1,248 @@
+use clap::ValueEnum;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::{WebPEncoder, WebPQuality};
+use image::imageops::{self, FilterType};
+use image::{ColorType, DynamicImage, GenericImageView, ImageResult, Rgba, RgbaImage};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::dataset::{caption_path, DatasetEntry};
+use crate::resolution::parse_size;
+
+// The crate default of 75 visibly degrades the images
+const JPEG_QUALITY: u8 = 95;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ResizeMode {
+    // Scaled to fit inside the size, the aspect ratio is kept so a side can be shorter
+    Fit,
+    // Scaled to cover the size and cropped in the center
+    Fill,
+    // Cropped in the center without scaling
+    CenterCrop,
+    // Scaled to fit inside the size and padded with the pad color
+    Pad,
+    // Scaled so the shorter side is as long as the smaller side of the size
+    ShorterSide,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    pub(crate) fn filter_type(self) -> FilterType {
+        match self {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ResizeOptions {
+    pub mode: ResizeMode,
+    pub width: u32,
+    pub height: u32,
+    pub filter: ResizeFilter,
+    pub pad_color: [u8; 4],
+}
+
+// Size of an image before and after the resize
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Resized {
+    pub from: (u32, u32),
+    pub to: (u32, u32),
+}
+
+impl ResizeOptions {
+    // Size of the resized image, without the padding
+    fn scaled_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let scale_x = self.width as f64 / width as f64;
+        let scale_y = self.height as f64 / height as f64;
+        let scale = match self.mode {
+            ResizeMode::Fit | ResizeMode::Pad => scale_x.min(scale_y),
+            ResizeMode::Fill => scale_x.max(scale_y),
+            ResizeMode::CenterCrop => 1.0,
+            ResizeMode::ShorterSide => {
+                self.width.min(self.height) as f64 / width.min(height) as f64
+            }
+        };
+        let scaled = |side: u32| ((side as f64 * scale).round() as u32).max(1);
+        (scaled(width), scaled(height))
+    }
+
+    pub fn output_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let (scaled_width, scaled_height) = self.scaled_size(width, height);
+        match self.mode {
+            ResizeMode::Fit | ResizeMode::ShorterSide => (scaled_width, scaled_height),
+            ResizeMode::Fill | ResizeMode::Pad => (self.width, self.height),
+            // A smaller image is not enlarged
+            ResizeMode::CenterCrop => (width.min(self.width), height.min(self.height)),
+        }
+    }
+
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        let (width, height) = img.dimensions();
+        let (scaled_width, scaled_height) = self.scaled_size(width, height);
+        let scaled = if (scaled_width, scaled_height) == (width, height) {
+            img.clone()
+        } else {
+            img.resize_exact(scaled_width, scaled_height, self.filter.filter_type())
+        };
+
+        let (out_width, out_height) = self.output_size(width, height);
+        match self.mode {
+            ResizeMode::Fit | ResizeMode::ShorterSide => scaled,
+            ResizeMode::Fill | ResizeMode::CenterCrop => {
+                let x = scaled_width.saturating_sub(out_width) / 2;
+                let y = scaled_height.saturating_sub(out_height) / 2;
+                scaled.crop_imm(x, y, out_width, out_height)
+            }
+            ResizeMode::Pad => {
+                let mut canvas = RgbaImage::from_pixel(out_width, out_height, Rgba(self.pad_color));
+                let x = out_width.saturating_sub(scaled_width) / 2;
+                let y = out_height.saturating_sub(scaled_height) / 2;
+                imageops::overlay(&mut canvas, &scaled.to_rgba8(), x as i64, y as i64);
+                DynamicImage::ImageRgba8(canvas)
+            }
+        }
+    }
+}
+
+// Writes the resized image in the output folder with the same name, the caption is copied along
+pub fn resize_file(
+    entry: &DatasetEntry,
+    output: &Path,
+    options: &ResizeOptions,
+) -> Result<Resized, String> {
+    let img = image::open(entry.image_path()).map_err(|e| e.to_string())?;
+    let resized = options.apply(&img);
+
+    let file_name = entry.image_path().file_name().ok_or("Invalid file name")?;
+    let out_path = output.join(file_name);
+    save_image(&resized, &out_path)?;
+    if entry.has_caption() {
+        fs::copy(entry.caption_path(), caption_path(&out_path))
+            .map_err(|e| format!("Failed to copy the caption: {}", e))?;
+    }
+
+    Ok(Resized {
+        from: img.dimensions(),
+        to: resized.dimensions(),
+    })
+}
+
+// The format is chosen by the extension of the path. Jpeg and webp are written at a high quality,
+// an image cropped in place would otherwise lose some of it at every crop
+pub fn save_image(img: &DynamicImage, path: &Path) -> Result<(), String> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .unwrap_or_default();
+    let saved = match extension.as_str() {
+        "jpg" | "jpeg" => encode(path, |file| {
+            // Jpeg has no alpha channel
+            let rgb = img.to_rgb8();
+            JpegEncoder::new_with_quality(file, JPEG_QUALITY).encode_image(&rgb)
+        }),
+        "webp" => encode(path, |file| {
+            let rgba = img.to_rgba8();
+            let (width, height) = rgba.dimensions();
+            WebPEncoder::new_with_quality(file, WebPQuality::lossless()).encode(
+                &rgba,
+                width,
+                height,
+                ColorType::Rgba8,
+            )
+        }),
+        _ => img.save(path),
+    };
+    saved.map_err(|e| format!("Failed to save {}: {}", path.display(), e))
+}
+
+fn encode(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> ImageResult<()>,
+) -> ImageResult<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write(&mut file)?;
+    file.flush()?;
+    Ok(())
+}
+
+// The images are split between the threads, the results are in the same order of the entries
+pub fn resize_all(
+    entries: &[DatasetEntry],
+    output: &Path,
+    options: &ResizeOptions,
+    threads: usize,
+) -> Vec<Result<Resized, String>> {
+    run_parallel(entries, threads, |entry| {
+        resize_file(entry, output, options)
+    })
+}
+
+// Calls the function for every item on the given number of threads, the results keep the order of the items
+pub fn run_parallel<T: Sync, R: Send>(
+    items: &[T],
+    threads: usize,
+    function: impl Fn(&T) -> R + Sync,
+) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<Option<R>>>());
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let item = match items.get(index) {
+                    Some(item) => item,
+                    None => return,
+                };
+                let result = function(item);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("Every item is processed"))
+        .collect()
+}
+
+// "1024x768", or "1024" for a square
+pub fn parse_target_size(text: &str) -> Result<(u32, u32), String> {
+    match text.trim().parse::<u32>() {
+        Ok(side) if side > 0 => Ok((side, side)),
+        _ => parse_size(text),
+    }
+}
+
+// "#rrggbb" or "#rrggbbaa"
+pub fn parse_color(text: &str) -> Result<[u8; 4], String> {
+    let hex = text.trim().trim_start_matches('#');
+    let invalid = || format!("Invalid color \"{}\", expected like #ffffff", text);
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let mut color = [255; 4];
+    for (i, channel) in color.iter_mut().enumerate().take(hex.len() / 2) {
+        *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(color)
+}
@@ -0,0 +1,131 @@
+use std::fmt;
+
+// Aspect ratios closer than this are the same, images are seldom exactly 4:3 after resizing
+const ASPECT_TOLERANCE: f64 = 0.01;
+
+// The sizes the images of a dataset should have, width and height in pixels
+#[derive(Clone, Debug, PartialEq)]
+pub enum Resolution {
+    Exact(u32, u32),
+    // Both sides at least this big
+    Minimum(u32, u32),
+    // Width and height of every allowed ratio, like 4:3
+    AspectRatios(Vec<(u32, u32)>),
+    // The image must have one of these sizes
+    Buckets(Vec<(u32, u32)>),
+}
+
+impl Default for Resolution {
+    fn default() -> Self {
+        Resolution::Exact(512, 512)
+    }
+}
+
+impl Resolution {
+    // "512x512", "min:1024x1024", "aspect:1:1,4:3,3:4" or "buckets:1024x1024,1152x896,896x1152"
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let (kind, value) = text.split_once(':').unwrap_or(("exact", text));
+        let list = |parse: fn(&str) -> Result<(u32, u32), String>| {
+            let sizes = value
+                .split(',')
+                .map(parse)
+                .collect::<Result<Vec<_>, String>>()?;
+            Ok::<_, String>(sizes)
+        };
+        match kind.trim().to_lowercase().as_str() {
+            "exact" => parse_size(value).map(|(w, h)| Resolution::Exact(w, h)),
+            "min" => parse_size(value).map(|(w, h)| Resolution::Minimum(w, h)),
+            "aspect" => list(parse_ratio).map(Resolution::AspectRatios),
+            "buckets" => list(parse_size).map(Resolution::Buckets),
+            _ => Err(format!(
+                "Unknown resolution \"{}\", expected exact, min, aspect or buckets",
+                kind
+            )),
+        }
+    }
+
+    // Err has the reason why the size is not allowed
+    pub fn check(&self, width: u32, height: u32) -> Result<(), String> {
+        let ok = match self {
+            Resolution::Exact(w, h) => width == *w && height == *h,
+            Resolution::Minimum(w, h) => width >= *w && height >= *h,
+            Resolution::AspectRatios(ratios) => {
+                let aspect = width as f64 / height.max(1) as f64;
+                ratios.iter().any(|(w, h)| {
+                    let ratio = *w as f64 / *h as f64;
+                    (aspect / ratio - 1.0).abs() <= ASPECT_TOLERANCE
+                })
+            }
+            Resolution::Buckets(sizes) => sizes.contains(&(width, height)),
+        };
+        if ok {
+            return Ok(());
+        }
+
+        let reason = match self {
+            Resolution::Exact(w, h) => format!("{}x{} instead of {}x{}", width, height, w, h),
+            Resolution::Minimum(w, h) => {
+                format!("{}x{} is smaller than {}x{}", width, height, w, h)
+            }
+            Resolution::AspectRatios(_) => format!(
+                "aspect ratio {:.3} ({}x{}) is not one of {}",
+                width as f64 / height.max(1) as f64,
+                width,
+                height,
+                self.sizes()
+            ),
+            Resolution::Buckets(_) => {
+                format!(
+                    "{}x{} is not one of the buckets {}",
+                    width,
+                    height,
+                    self.sizes()
+                )
+            }
+        };
+        Err(reason)
+    }
+
+    fn sizes(&self) -> String {
+        let (sizes, separator) = match self {
+            Resolution::Exact(w, h) | Resolution::Minimum(w, h) => return format!("{}x{}", w, h),
+            Resolution::AspectRatios(ratios) => (ratios, ":"),
+            Resolution::Buckets(sizes) => (sizes, "x"),
+        };
+        sizes
+            .iter()
+            .map(|(w, h)| format!("{}{}{}", w, separator, h))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Resolution::Exact(..) => write!(f, "{}", self.sizes()),
+            Resolution::Minimum(..) => write!(f, "at least {}", self.sizes()),
+            Resolution::AspectRatios(_) => write!(f, "aspect ratio {}", self.sizes()),
+            Resolution::Buckets(_) => write!(f, "one of {}", self.sizes()),
+        }
+    }
+}
+
+// Like "1024x768"
+pub fn parse_size(text: &str) -> Result<(u32, u32), String> {
+    parse_pair(text, 'x')
+        .ok_or_else(|| format!("Invalid size \"{}\", expected like 1024x768", text))
+}
+
+fn parse_ratio(text: &str) -> Result<(u32, u32), String> {
+    parse_pair(text, ':')
+        .ok_or_else(|| format!("Invalid aspect ratio \"{}\", expected like 4:3", text))
+}
+
+fn parse_pair(text: &str, separator: char) -> Option<(u32, u32)> {
+    let (a, b) = text.trim().split_once(separator)?;
+    let a: u32 = a.trim().parse().ok()?;
+    let b: u32 = b.trim().parse().ok()?;
+    (a > 0 && b > 0).then_some((a, b))
+}
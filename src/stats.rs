@@ -0,0 +1,188 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::caption::{Caption, CaptionFormat};
+use crate::dataset::{caption_path, get_files_in_folder, is_caption, is_image};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+    // Fraction of the captions containing the tag
+    pub share: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CoOccurrence {
+    pub first: String,
+    pub second: String,
+    pub count: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LengthBucket {
+    // Number of tags of the captions in this bucket, both ends included
+    pub from: usize,
+    pub to: usize,
+    pub count: usize,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LengthStats {
+    pub min_tags: usize,
+    pub max_tags: usize,
+    pub mean_tags: f64,
+    pub median_tags: usize,
+    pub mean_chars: f64,
+    pub histogram: Vec<LengthBucket>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DatasetStats {
+    pub images: usize,
+    pub captions: usize,
+    // Captions that are free text instead of a list of tags
+    pub prose_captions: usize,
+    pub tags: Vec<TagCount>,
+    pub co_occurrences: Vec<CoOccurrence>,
+    pub caption_lengths: LengthStats,
+    pub images_without_captions: Vec<PathBuf>,
+    pub captions_without_images: Vec<PathBuf>,
+}
+
+impl DatasetStats {
+    // Reads every caption of the folder, only the `top` most common pairs of tags are kept
+    pub fn compute(dir: &Path, format: &CaptionFormat, top: usize) -> Self {
+        let filter = |file: &Path| is_image(file) || is_caption(file);
+        let mut images = Vec::new();
+        let mut captions = Vec::new();
+        for file in get_files_in_folder(dir, Some(&filter)) {
+            let path = file.path();
+            if is_image(&path) {
+                images.push(path);
+            } else {
+                captions.push(path);
+            }
+        }
+        images.sort();
+        captions.sort();
+
+        let mut images_without_captions = Vec::new();
+        for image in &images {
+            let caption = fs::read_to_string(caption_path(image)).unwrap_or_default();
+            if caption.trim().is_empty() {
+                images_without_captions.push(image.clone());
+            }
+        }
+
+        let image_stems: HashSet<PathBuf> =
+            images.iter().map(|img| img.with_extension("")).collect();
+        let captions_without_images = captions
+            .iter()
+            .filter(|caption| !image_stems.contains(&caption.with_extension("")))
+            .cloned()
+            .collect();
+
+        let mut tag_counts: HashMap<String, usize> = HashMap::new();
+        let mut pair_counts: HashMap<(String, String), usize> = HashMap::new();
+        let mut tag_lengths = Vec::new();
+        let mut char_lengths = Vec::new();
+        let mut prose_captions = 0;
+        for file in &captions {
+            let text = match fs::read_to_string(file) {
+                Ok(text) => text,
+                Err(_) => continue,
+            };
+            char_lengths.push(text.trim().chars().count());
+
+            let caption = Caption::parse(&text, format);
+            if caption.is_prose() {
+                prose_captions += 1;
+                continue;
+            }
+
+            let mut names: Vec<&String> = caption.tags().iter().map(|tag| &tag.name).collect();
+            names.sort();
+            names.dedup();
+            tag_lengths.push(names.len());
+            for (i, name) in names.iter().enumerate() {
+                *tag_counts.entry(name.to_string()).or_insert(0) += 1;
+                for other in &names[i + 1..] {
+                    let key = (name.to_string(), other.to_string());
+                    *pair_counts.entry(key).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let total = captions.len().max(1) as f64;
+        let mut tags: Vec<TagCount> = tag_counts
+            .into_iter()
+            .map(|(tag, count)| TagCount {
+                tag,
+                count,
+                share: count as f64 / total,
+            })
+            .collect();
+        tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+
+        let mut co_occurrences: Vec<CoOccurrence> = pair_counts
+            .into_iter()
+            .map(|((first, second), count)| CoOccurrence {
+                first,
+                second,
+                count,
+            })
+            .collect();
+        co_occurrences.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.first.cmp(&b.first))
+                .then_with(|| a.second.cmp(&b.second))
+        });
+        co_occurrences.truncate(top);
+
+        Self {
+            images: images.len(),
+            captions: captions.len(),
+            prose_captions,
+            tags,
+            co_occurrences,
+            caption_lengths: length_stats(tag_lengths, &char_lengths),
+            images_without_captions,
+            captions_without_images,
+        }
+    }
+}
+
+const HISTOGRAM_BUCKETS: usize = 10;
+
+fn length_stats(mut tag_lengths: Vec<usize>, char_lengths: &[usize]) -> LengthStats {
+    let mut stats = LengthStats::default();
+    if !char_lengths.is_empty() {
+        stats.mean_chars = char_lengths.iter().sum::<usize>() as f64 / char_lengths.len() as f64;
+    }
+    if tag_lengths.is_empty() {
+        return stats;
+    }
+
+    tag_lengths.sort();
+    stats.min_tags = tag_lengths[0];
+    stats.max_tags = tag_lengths[tag_lengths.len() - 1];
+    stats.median_tags = tag_lengths[tag_lengths.len() / 2];
+    stats.mean_tags = tag_lengths.iter().sum::<usize>() as f64 / tag_lengths.len() as f64;
+
+    let width = ((stats.max_tags - stats.min_tags) / HISTOGRAM_BUCKETS + 1).max(1);
+    let mut from = stats.min_tags;
+    while from <= stats.max_tags {
+        let to = from + width - 1;
+        let count = tag_lengths
+            .iter()
+            .filter(|len| **len >= from && **len <= to)
+            .count();
+        stats.histogram.push(LengthBucket { from, to, count });
+        from += width;
+    }
+    stats
+}
@@ -0,0 +1,101 @@
+use clap::ValueEnum;
+use regex::{NoExpand, Regex, RegexBuilder};
+
+use crate::caption::{Caption, CaptionFormat, Tag};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MatchMode {
+    // The pattern is searched as a plain string anywhere in the caption
+    Plain,
+    // The pattern is a regex, the replacement can use capture groups like $1 or ${name}
+    Regex,
+    // The pattern must match a whole tag, an empty replacement removes the tag. Prose captions are not modified
+    Tag,
+}
+
+pub struct Substitution {
+    regex: Regex,
+    replacement: String,
+    mode: MatchMode,
+    format: CaptionFormat,
+}
+
+impl Substitution {
+    pub fn new(
+        pattern: &str,
+        replacement: &str,
+        mode: MatchMode,
+        case_insensitive: bool,
+        format: CaptionFormat,
+    ) -> Result<Self, String> {
+        if pattern.is_empty() {
+            return Err("The string to replace can not be empty".to_string());
+        }
+
+        let pattern = match mode {
+            MatchMode::Plain => regex::escape(pattern),
+            MatchMode::Regex => pattern.to_string(),
+            MatchMode::Tag => format!("^{}$", regex::escape(pattern.trim())),
+        };
+
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|e| format!("Invalid pattern: {}", e))?;
+
+        Ok(Self {
+            regex,
+            replacement: replacement.to_string(),
+            mode,
+            format,
+        })
+    }
+
+    pub fn apply(&self, caption: &str) -> String {
+        match self.mode {
+            MatchMode::Plain => self
+                .regex
+                .replace_all(caption, NoExpand(&self.replacement))
+                .to_string(),
+            MatchMode::Regex => self
+                .regex
+                .replace_all(caption, self.replacement.as_str())
+                .to_string(),
+            MatchMode::Tag => self.apply_to_tags(caption),
+        }
+    }
+
+    fn apply_to_tags(&self, caption: &str) -> String {
+        let parsed = Caption::parse(caption, &self.format);
+        if !parsed
+            .tags()
+            .iter()
+            .any(|tag| self.regex.is_match(&tag.name))
+        {
+            return caption.to_string();
+        }
+
+        let replacement = Tag::parse(&self.replacement);
+        let mut tags: Vec<Tag> = Vec::new();
+        for tag in parsed.tags() {
+            let tag = if self.regex.is_match(&tag.name) {
+                if replacement.name.is_empty() {
+                    continue;
+                }
+                // The weight of the replaced tag is kept, unless the replacement has its own
+                Tag {
+                    name: replacement.name.clone(),
+                    weight: replacement.weight.clone().or_else(|| tag.weight.clone()),
+                }
+            } else {
+                tag.clone()
+            };
+            // The replacement could already be in the caption
+            if self.format.dedup && tags.iter().any(|t| t.name == tag.name) {
+                continue;
+            }
+            tags.push(tag);
+        }
+        Caption::Tags(tags).serialize(&self.format)
+    }
+}
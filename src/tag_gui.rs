@@ -1,13 +1,122 @@
 use eframe::egui;
-
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ai_utils::caption::{Caption, CaptionFormat};
+use ai_utils::caption_edit::{CaptionEdit, EditOutcome, TagOperation};
+use ai_utils::caption_writer::CaptionWriter;
+use ai_utils::crop::{crop_file, CropRecord};
+use ai_utils::dataset::caption_path;
+use ai_utils::query::Query;
+use ai_utils::resize::ResizeFilter;
+use ai_utils::resolution::Resolution;
+use ai_utils::vocabulary::Vocabulary;
+use ai_utils::Dataset;
 
+pub mod autocomplete;
+pub mod bucket_panel;
+pub mod bulk_edit;
+pub mod crop_tool;
+pub mod grid_view;
+pub mod history;
+pub mod image_buffer;
 pub mod image_loader;
+pub mod navigator;
+pub mod search_bar;
+pub mod shortcuts;
+pub mod tag_editor;
+pub mod tag_panel;
+pub mod thumbnails;
+use autocomplete::Autocomplete;
+use bucket_panel::BucketPanel;
+use bulk_edit::BulkEdit;
+use crop_tool::{CropEvent, CropTool};
+use grid_view::{GridView, ViewMode};
+use history::History;
+use image_buffer::BufferOptions;
 use image_loader::CaptionedImg;
+use navigator::{Jump, Navigator};
+use search_bar::{SearchBar, SearchEvent};
+use shortcuts::{Action, Shortcuts};
+use tag_editor::{EditorMode, TagEditor};
+use tag_panel::TagPanel;
+
+pub const WINDOW_TITLE: &str = "Dataset images tagging util";
+
+// Typing is recorded in the undo history after this many seconds without changes
+const UNDO_PAUSE: f64 = 1.0;
+
+// When the edited captions are written without pressing Save
+#[derive(Clone, Copy, Debug)]
+pub struct Autosave {
+    // Saves the caption of the image being left
+    pub on_navigate: bool,
+    // Saves every edited caption after this many seconds without changes
+    pub idle: Option<f64>,
+    pub on_close: bool,
+}
+
+impl Default for Autosave {
+    fn default() -> Self {
+        Self {
+            on_navigate: true,
+            idle: None,
+            on_close: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GuiOptions {
+    // In read only mode the captions are never written to disk
+    pub read_only: bool,
+    pub writer: CaptionWriter,
+    pub caption_format: CaptionFormat,
+    pub buffer: BufferOptions,
+    pub autosave: Autosave,
+    // Images with another size are flagged
+    pub resolution: Resolution,
+    // Where the crop tool writes the cropped images, None replaces the images
+    pub crop_output: Option<PathBuf>,
+}
 
 pub struct TagGui {
     img_loader: image_loader::ImageLoader,
+    options: GuiOptions,
     current_image: CaptionedImg,
+    editor_mode: EditorMode,
+    tag_editor: TagEditor,
+    vocabulary: Vocabulary,
+    autocomplete: Autocomplete,
+    tag_panel: TagPanel,
+    bucket_panel: BucketPanel,
+    crop_tool: CropTool,
+    search_bar: SearchBar,
+    // Names of the images matching the search, None if every image can be shown
+    filter: Option<HashSet<String>>,
+    navigator: Navigator,
+    view_mode: ViewMode,
+    grid: GridView,
+    bulk_edit: BulkEdit,
+    shortcuts: Shortcuts,
+    show_shortcuts: bool,
+    history: History,
+    // The caption being typed and when it last changed
+    typing: (String, f64),
+    // Edited captions of the images left without saving, the current one is never here
+    unsaved: HashMap<String, String>,
+    // Why the last caption could not be written, cleared by the next save that works
+    save_error: Option<String>,
+    // The window asking what to do with the unsaved captions is open
+    confirm_close: bool,
+    close_confirmed: bool,
+    title: String,
+    // The caption of every image as it is on disk, used to keep the tag counts up to date
+    saved_captions: Vec<(String, String)>,
+    // Same order as the captions
+    image_paths: Vec<PathBuf>,
     persistent_txt: String,
     desired_rows: usize,
     loaded_first_img: bool,
@@ -15,17 +124,56 @@ pub struct TagGui {
 }
 
 impl TagGui {
-    pub fn new(path: &str) -> Self {
-        let mut container = Vec::new();
-        TagGui::setup_file_list(&mut container, path);
-        if container.is_empty() {
+    pub fn new(
+        dataset: Dataset,
+        vocabulary: Vocabulary,
+        shortcuts: Shortcuts,
+        options: GuiOptions,
+    ) -> Self {
+        if dataset.is_empty() {
             panic!("No files found in directory");
         }
-        // println!("{:?}", container); let bytes = include_bytes!("../assets/no_img.png");
+
+        let saved_captions: Vec<(String, String)> = dataset
+            .entries()
+            .iter()
+            .map(|entry| (entry.name(), entry.read_caption()))
+            .collect();
+        let image_paths: Vec<_> = dataset
+            .entries()
+            .iter()
+            .map(|entry| entry.image_path().to_path_buf())
+            .collect();
+        let crop_tool = CropTool::new(&options.resolution);
 
         TagGui {
-            img_loader: image_loader::ImageLoader::new(path.to_string(), container),
+            img_loader: image_loader::ImageLoader::new(dataset, options.buffer),
+            options,
             current_image: image_loader::ImageLoader::get_std_img(),
+            editor_mode: EditorMode::Raw,
+            tag_editor: TagEditor::new(),
+            vocabulary,
+            autocomplete: Autocomplete::new(),
+            tag_panel: TagPanel::new(),
+            bucket_panel: BucketPanel::new(image_paths.clone()),
+            crop_tool,
+            search_bar: SearchBar::new(),
+            filter: None,
+            navigator: Navigator::new(),
+            view_mode: ViewMode::Single,
+            grid: GridView::new(image_paths.clone()),
+            bulk_edit: BulkEdit::new(),
+            shortcuts,
+            show_shortcuts: false,
+            history: History::new(&saved_captions),
+            typing: (String::new(), 0.0),
+            unsaved: HashMap::new(),
+            save_error: None,
+            confirm_close: false,
+            close_confirmed: false,
+            title: WINDOW_TITLE.to_string(),
+            saved_captions,
+            image_paths,
             persistent_txt: String::new(),
             desired_rows: 35,
             loaded_first_img: false,
@@ -33,27 +181,512 @@ impl TagGui {
         }
     }
 
-    fn setup_file_list(container: &mut Vec<String>, dir: &str) {
-        let files = fs::read_dir(dir).unwrap();
+    // Every caption has to be saved through here, so the tag counts follow the captions on disk.
+    // A caption that could not be written stays unsaved, the current one stays dirty
+    fn save_caption(&mut self, name: &str, caption: &str) {
+        self.history.record(name, caption);
+        if let Err(e) = self.write_caption(name, caption) {
+            if name != self.current_image.name() {
+                self.unsaved.insert(name.to_string(), caption.to_string());
+            }
+            self.save_error = Some(e);
+            return;
+        }
+        self.img_loader.caption_saved(name, caption);
+        self.unsaved.remove(name);
+        self.save_error = None;
 
-        for file in files {
-            let file = file.unwrap();
+        let format = self.options.caption_format;
+        let saved = self.saved_captions.iter_mut().find(|(n, _)| n == name);
+        if let Some((_, saved)) = saved {
+            if saved != caption {
+                self.vocabulary
+                    .remove_caption(&Caption::parse(saved, &format));
+                self.vocabulary
+                    .add_caption(&Caption::parse(caption, &format));
+                *saved = caption.to_string();
+                self.tag_panel.refresh(&self.saved_captions, &format);
+            }
+        }
+    }
 
-            // In the directory we expect 2 files, an image, and a txt file containing the tags
-            let name = file.file_name().to_str().unwrap().to_string();
-            if name.ends_with(".png") || name.ends_with(".jpg") || name.ends_with(".jpeg") {
-                let name = file.file_name().to_str().unwrap().to_string();
+    fn write_caption(&self, name: &str, caption: &str) -> Result<(), String> {
+        if self.options.read_only {
+            return Err(format!("Dry run, the caption of {} is not saved", name));
+        }
+        let position = self
+            .saved_captions
+            .iter()
+            .position(|(n, _)| n == name)
+            .ok_or_else(|| format!("{} is not in the folder", name))?;
+        let path = caption_path(&self.image_paths[position]);
+        self.options
+            .writer
+            .write(&path, caption)
+            .map_err(|e| format!("Failed to save {}: {}", path.display(), e))
+    }
 
-                // We remove the .png extension
-                let name = name[..name.len() - 4].to_string();
-                container.push(name);
+    // The caption of the current image is different from the one on disk
+    fn is_dirty(&self) -> bool {
+        let name = self.current_image.name();
+        self.saved_captions
+            .iter()
+            .find(|(n, _)| *n == name)
+            .is_some_and(|(_, saved)| *saved != self.current_image.caption)
+    }
+
+    fn unsaved_count(&self) -> usize {
+        self.unsaved.len() + self.is_dirty() as usize
+    }
+
+    fn save_all(&mut self) {
+        self.save_current();
+        let unsaved: Vec<(String, String)> = self.unsaved.drain().collect();
+        for (name, caption) in unsaved {
+            self.save_caption(&name, &caption);
+        }
+    }
+
+    // Without autosave on navigation the edited caption is kept in memory until it is saved
+    fn leave_image(&mut self, name: &str, caption: &str) {
+        if self.options.autosave.on_navigate {
+            self.save_caption(name, caption);
+            return;
+        }
+        let saved = self.saved_captions.iter().find(|(n, _)| n == name);
+        if saved.is_some_and(|(_, saved)| saved != caption) {
+            self.history.record(name, caption);
+            self.unsaved.insert(name.to_string(), caption.to_string());
+        }
+    }
+
+    // Saves after some time without changes, the time restarts at every edit
+    fn autosave_idle(&mut self, ctx: &egui::Context) {
+        let delay = match self.options.autosave.idle {
+            Some(delay) => delay,
+            None => return,
+        };
+        if self.unsaved_count() == 0 {
+            return;
+        }
+        let idle = ctx.input(|i| i.time) - self.typing.1;
+        if idle >= delay {
+            self.save_all();
+        } else {
+            ctx.request_repaint_after(Duration::from_secs_f64(delay - idle));
+        }
+    }
+
+    fn update_title(&mut self, frame: &mut eframe::Frame) {
+        let title = match self.unsaved_count() {
+            0 => WINDOW_TITLE.to_string(),
+            unsaved => format!("{} - {} unsaved", WINDOW_TITLE, unsaved),
+        };
+        if title != self.title {
+            frame.set_window_title(&title);
+            self.title = title;
+        }
+    }
+
+    fn show_close_dialog(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        if !self.confirm_close {
+            return;
+        }
+        let unsaved = self.unsaved_count();
+        egui::Window::new("Unsaved captions")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!("{} captions are not saved", unsaved));
+                if let Some(e) = &self.save_error {
+                    ui.colored_label(ui.visuals().error_fg_color, e);
+                }
+                ui.horizontal(|ui| {
+                    // The window stays open if a caption could not be written
+                    if ui.button("Save and quit").clicked() {
+                        self.save_all();
+                        if self.unsaved_count() == 0 {
+                            self.close_confirmed = true;
+                            frame.close();
+                        }
+                    }
+                    if ui.button("Quit without saving").clicked() {
+                        self.close_confirmed = true;
+                        frame.close();
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.confirm_close = false;
+                    }
+                });
+            });
+    }
+
+    // Changes the caption of the current image as a single step of the undo history
+    fn set_caption(&mut self, caption: String) {
+        let name = self.current_image.name();
+        self.history.record(&name, &self.current_image.caption);
+        self.history.record(&name, &caption);
+        self.current_image.caption = caption;
+    }
+
+    fn undo(&mut self) {
+        let name = self.current_image.name();
+        if let Some(caption) = self.history.undo(&name, &self.current_image.caption) {
+            self.current_image.caption = caption;
+        }
+    }
+
+    fn redo(&mut self) {
+        let name = self.current_image.name();
+        if let Some(caption) = self.history.redo(&name, &self.current_image.caption) {
+            self.current_image.caption = caption;
+        }
+    }
+
+    // Goes back to the caption the image had when the folder was opened, it can be undone
+    fn revert(&mut self) {
+        let name = self.current_image.name();
+        if let Some(opened) = self.history.opened(&name) {
+            self.set_caption(opened.to_string());
+        }
+    }
+
+    // Typing is recorded after a pause, so undo does not go back one letter at a time
+    fn record_typing(&mut self, ctx: &egui::Context) {
+        let now = ctx.input(|i| i.time);
+        let caption = &self.current_image.caption;
+        if *caption != self.typing.0 {
+            self.typing = (caption.clone(), now);
+        } else if now - self.typing.1 >= UNDO_PAUSE {
+            self.history.record(&self.current_image.name(), caption);
+        } else {
+            let left = UNDO_PAUSE - (now - self.typing.1);
+            ctx.request_repaint_after(Duration::from_secs_f64(left));
+        }
+    }
+
+    fn save_current(&mut self) {
+        let img_name = self.current_image.name();
+        let img_caption = self.current_image.caption();
+        self.save_caption(&img_name, &img_caption);
+    }
+
+    fn previous(&mut self) {
+        let img = self.img_loader.get_previous();
+        self.replace_image(img);
+    }
+
+    fn next(&mut self) {
+        let img = self.img_loader.get_next();
+        self.replace_image(img);
+    }
+
+    fn go_to(&mut self, name: &str) {
+        if name == self.current_image.name() {
+            return;
+        }
+        let img = self.img_loader.get_by_name(name);
+        self.replace_image(img);
+    }
+
+    fn go_to_index(&mut self, index: usize) {
+        if index == self.img_loader.position().index {
+            return;
+        }
+        let img = self.img_loader.get_at(index);
+        self.replace_image(img);
+    }
+
+    // Shows the loaded image and saves the caption of the one being replaced
+    fn replace_image(&mut self, img: Option<CaptionedImg>) {
+        let mut img = match img {
+            Some(img) => img,
+            None => {
+                if self.options.autosave.on_navigate {
+                    self.save_current();
+                }
+                return;
             }
+        };
+        if let Some(caption) = self.unsaved.remove(&img.name()) {
+            img.caption = caption;
         }
+
+        let img_name = self.current_image.name();
+        let img_caption = self.current_image.caption();
+        self.can_open_warinig = true;
+        self.current_image = img;
+        self.leave_image(&img_name, &img_caption);
+    }
+
+    // Previous and Next move only between the images matching the query, if the current image
+    // does not match the first one that does is shown
+    fn apply_filter(&mut self, query: Option<Query>) {
+        let format = self.options.caption_format;
+        let names: Option<HashSet<String>> = query.map(|query| {
+            self.saved_captions
+                .iter()
+                .filter(|(_, caption)| query.matches(caption, &format))
+                .map(|(name, _)| name.clone())
+                .collect()
+        });
+        self.search_bar.matches = names.as_ref().map(|names| names.len());
+        self.filter = names.clone();
+        self.grid.clear_selection();
+
+        let img_name = self.current_image.name();
+        let keep_current = names.as_ref().is_none_or(|names| names.contains(&img_name));
+        self.img_loader.set_filter(names);
+
+        if keep_current {
+            // Only moves the loader back to the current image, the caption could have been edited
+            self.img_loader.get_by_name(&img_name);
+            if self.options.autosave.on_navigate {
+                self.save_current();
+            }
+        } else {
+            let img = self.img_loader.get_next();
+            self.replace_image(img);
+        }
+    }
+
+    fn show_grid(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label(format!("{} selected", self.grid.selected_count()));
+            if ui.button("Select all").clicked() {
+                self.grid
+                    .select_all(&self.saved_captions, self.filter.as_ref());
+            }
+            if ui.button("Select none").clicked() {
+                self.grid.clear_selection();
+            }
+            ui.separator();
+            if let Some(operation) = self.bulk_edit.show(ui, self.grid.selected_count()) {
+                self.apply_to_selection(operation);
+            }
+        });
+
+        let current = self.current_image.name();
+        let open = self.grid.show(
+            ui,
+            &self.saved_captions,
+            self.filter.as_ref(),
+            &current,
+            &self.options.caption_format,
+            &self.options.resolution,
+        );
+        if let Some(name) = open {
+            self.go_to(&name);
+            self.view_mode = ViewMode::Single;
+        }
+    }
+
+    // Adds the tag at the end of the caption or removes it, prose captions are left alone
+    fn toggle_quick_tag(&mut self, index: usize) {
+        let format = self.options.caption_format;
+        let tag = self.shortcuts.quick_tag(index).to_string();
+        let present = Caption::parse(&self.current_image.caption, &format)
+            .tags()
+            .iter()
+            .any(|t| t.name == tag);
+        let operation = if present {
+            TagOperation::Remove(tag)
+        } else {
+            TagOperation::AddEnd(tag)
+        };
+        let edit = CaptionEdit::new(vec![operation], format);
+        if let Ok(EditOutcome::Changed(caption)) =
+            edit.map(|edit| edit.apply(&self.current_image.caption))
+        {
+            self.set_caption(caption);
+        }
+    }
+
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        for action in self.shortcuts.pressed(ctx) {
+            match action {
+                Action::Next => self.next(),
+                Action::Previous => self.previous(),
+                Action::Save => self.save_all(),
+                Action::Undo => self.undo(),
+                Action::Redo => self.redo(),
+                Action::Search => self.search_bar.focus(ctx),
+                Action::ToggleGrid => {
+                    self.view_mode = match self.view_mode {
+                        ViewMode::Single => ViewMode::Grid,
+                        ViewMode::Grid => ViewMode::Single,
+                    }
+                }
+                Action::Help => self.show_shortcuts = !self.show_shortcuts,
+                // The grid has no current caption being edited
+                Action::QuickTag(index) => {
+                    if self.view_mode == ViewMode::Single {
+                        self.toggle_quick_tag(index);
+                    }
+                }
+            }
+        }
+    }
+
+    fn apply_to_selection(&mut self, operation: TagOperation) {
+        let format = self.options.caption_format;
+        let edit = match CaptionEdit::new(vec![operation], format) {
+            Ok(edit) => edit,
+            Err(e) => {
+                self.bulk_edit.error = Some(e);
+                return;
+            }
+        };
+        self.bulk_edit.error = None;
+
+        // The current image could have unsaved changes, they would be lost
+        let current = self.current_image.name();
+        self.save_current();
+
+        // The edits of the images left without saving are kept, the current caption too if it
+        // could not be saved
+        let selected = self.grid.selected_names(&self.saved_captions);
+        let unsaved = &self.unsaved;
+        let current_caption = &self.current_image.caption;
+        let edits = edit.plan(
+            self.saved_captions
+                .iter()
+                .filter(|(name, _)| selected.contains(name))
+                .map(|(name, caption)| {
+                    let caption = match unsaved.get(name) {
+                        _ if *name == current => current_caption,
+                        Some(edited) => edited,
+                        None => caption,
+                    };
+                    (name.as_str(), caption.as_str())
+                }),
+        );
+        for file in &edits {
+            if let EditOutcome::Changed(caption) = &file.outcome {
+                self.save_caption(&file.name, caption);
+                if file.name == current {
+                    self.current_image.caption = caption.clone();
+                }
+            }
+        }
+        self.bulk_edit.report = Some(edits);
+    }
+
+    fn show_crop_tool(&mut self, ui: &mut egui::Ui) {
+        let destination = match &self.options.crop_output {
+            Some(output) => format!("Written to {}", output.display()),
+            None => "Replaces the image".to_string(),
+        };
+        ui.vertical(|ui| {
+            let max_size = egui::Vec2::splat(512.0);
+            match self
+                .crop_tool
+                .show(ui, &self.current_image, max_size, &destination)
+            {
+                Some(CropEvent::Save(record)) => self.save_crop(&record),
+                Some(CropEvent::Cancel) => self.crop_tool.active = false,
+                None => {}
+            }
+        });
+    }
+
+    // Crops the current image into the crop output folder, or over the image itself
+    fn save_crop(&mut self, record: &CropRecord) {
+        let name = self.current_image.name();
+        let position = match self.saved_captions.iter().position(|(n, _)| *n == name) {
+            Some(position) => position,
+            None => return,
+        };
+        let source = self.image_paths[position].clone();
+        if self.options.read_only {
+            println!("Dry run, crop of {} not saved", name);
+            self.crop_tool.message = Some(Ok("Dry run, the crop is not saved".to_string()));
+            return;
+        }
+
+        let result = match self.options.crop_output.clone() {
+            Some(output) => self.crop_to_output(&source, &output, record),
+            None => self.crop_in_place(&source, position, record),
+        };
+        if let Err(e) = &result {
+            println!("Failed to crop {}: {}", name, e);
+        }
+        self.crop_tool.message = Some(result);
+    }
+
+    fn crop_to_output(
+        &self,
+        source: &Path,
+        output: &Path,
+        record: &CropRecord,
+    ) -> Result<String, String> {
+        fs::create_dir_all(output)
+            .map_err(|e| format!("Failed to create {}: {}", output.display(), e))?;
+        let out_path = output.join(source.file_name().unwrap_or_default());
+        crop_file(source, &out_path, record, ResizeFilter::Lanczos3)?;
+        // The caption goes along as it is in the editor, even if it is not saved yet
+        self.options
+            .writer
+            .write(&caption_path(&out_path), &self.current_image.caption)
+            .map_err(|e| format!("Failed to write the caption: {}", e))?;
+        Ok(format!("Saved {}", out_path.display()))
+    }
+
+    fn crop_in_place(
+        &mut self,
+        source: &Path,
+        position: usize,
+        record: &CropRecord,
+    ) -> Result<String, String> {
+        let record = crop_file(source, source, record, ResizeFilter::Lanczos3)?;
+        let caption = self.current_image.caption.clone();
+        if let Some(mut img) = self.img_loader.reload(&self.current_image.name()) {
+            img.caption = caption;
+            self.current_image = img;
+        }
+
+        let (width, height) = record.cropped_size();
+        self.grid.image_changed(position);
+        self.bucket_panel.image_changed(position, (width, height));
+        Ok(format!("{} cropped to {}x{}", record.image, width, height))
     }
 }
 
 impl eframe::App for TagGui {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    // The window is closed only once the unsaved captions are saved or the user chose to lose them
+    fn on_close_event(&mut self) -> bool {
+        if self.options.autosave.on_close {
+            self.save_all();
+        }
+        if self.unsaved_count() > 0 && !self.close_confirmed {
+            self.confirm_close = true;
+            return false;
+        }
+        self.img_loader.stop();
+        true
+    }
+
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        // Side panels have to be added before the central one
+        let jump = self.tag_panel.show(
+            ctx,
+            &self.vocabulary,
+            &self.saved_captions,
+            &self.current_image.name(),
+            &self.options.caption_format,
+        );
+        if let Some(name) = jump {
+            self.go_to(&name);
+        }
+        self.bulk_edit.show_report(ctx);
+        let current = self.current_image.name();
+        if let Some(name) = self.bucket_panel.show(ctx, &self.saved_captions, &current) {
+            self.go_to(&name);
+        }
+        self.shortcuts.show_help(ctx, &mut self.show_shortcuts);
+        self.show_close_dialog(ctx, frame);
+
         egui::CentralPanel::default().show(ctx, |ui| {
             //ui.heading("Tagging Tool");
 
@@ -65,75 +698,214 @@ impl eframe::App for TagGui {
                 }
                 self.current_image = img.unwrap();
             }
+            // Before the widgets, so the keys used by a shortcut do not reach them
+            self.handle_shortcuts(ctx);
+            self.record_typing(ctx);
+            self.autosave_idle(ctx);
+            self.update_title(frame);
 
-            if self.current_image.is_wrong_size() && self.can_open_warinig {
-                egui::Window::new("My Window").show(ctx, |ui| {
-                    ui.label("This image is the not the right size");
-                    if ui.button("Close").clicked() {
-                        self.can_open_warinig = false;
-                    }
+            let total = self.saved_captions.len();
+            match self.search_bar.show(ui, total) {
+                Some(SearchEvent::Apply(query)) => self.apply_filter(Some(query)),
+                Some(SearchEvent::Clear) => self.apply_filter(None),
+                None => {}
+            }
+
+            let position = self.img_loader.position();
+            let filter = &self.filter;
+            let loader = &self.img_loader;
+            // The same images the loader can move through
+            let names = self
+                .saved_captions
+                .iter()
+                .map(|(name, _)| name)
+                .filter(|name| filter.as_ref().is_none_or(|filter| filter.contains(*name)))
+                .filter(|name| !loader.is_failed(name));
+            let current = self.current_image.name();
+            match self.navigator.show(ui, position, names, &current) {
+                Some(Jump::Index(index)) => self.go_to_index(index),
+                Some(Jump::Name(name)) => self.go_to(&name),
+                None => {}
+            }
+            ui.add_space(5.0);
+
+            let (width, height) = self.current_image.size();
+            let size_check = self.options.resolution.check(width, height);
+            if let (Err(reason), true) = (&size_check, self.can_open_warinig) {
+                egui::Window::new("Wrong size").show(ctx, |ui| {
+                    ui.label(format!("This image is not the right size: {}", reason));
+                    ui.weak(format!("Expected {}", self.options.resolution));
+                    ui.horizontal(|ui| {
+                        if ui.button("Crop").clicked() {
+                            self.crop_tool.active = true;
+                            self.view_mode = ViewMode::Single;
+                            self.can_open_warinig = false;
+                        }
+                        if ui.button("Close").clicked() {
+                            self.can_open_warinig = false;
+                        }
+                    });
                 });
             }
 
+            if let Some(e) = &self.save_error {
+                ui.colored_label(ui.visuals().error_fg_color, e);
+            }
+
             ui.horizontal(|ui| {
                 let std_button_size = egui::vec2(90.0, 30.0);
 
-                ui.label(format!("{}", self.current_image.name()));
+                if self.is_dirty() {
+                    ui.label(format!("{} *", self.current_image.name()))
+                        .on_hover_text("Caption not saved");
+                } else {
+                    ui.label(self.current_image.name());
+                }
+
+                let format = self.options.caption_format;
+                let caption = Caption::parse(&self.current_image.caption, &format);
+                if caption.is_prose() {
+                    ui.weak("prose");
+                } else {
+                    ui.weak(format!("{} tags", caption.tags().len()));
+                }
+                let size = format!("{}x{}", width, height);
+                match &size_check {
+                    Ok(()) => ui.weak(size),
+                    Err(reason) => ui
+                        .colored_label(ui.visuals().error_fg_color, size)
+                        .on_hover_text(reason),
+                };
+
+                ui.selectable_value(&mut self.view_mode, ViewMode::Single, "Image");
+                ui.selectable_value(&mut self.view_mode, ViewMode::Grid, "Grid");
+                ui.toggle_value(&mut self.crop_tool.active, "Crop");
+                if ui.button("Buckets").clicked() {
+                    self.bucket_panel.open = !self.bucket_panel.open;
+                }
+                if ui.button("?").on_hover_text("Keyboard shortcuts").clicked() {
+                    self.show_shortcuts = !self.show_shortcuts;
+                }
 
-                let available_width = ui.available_width() - std_button_size.x * 3.0 - 30.0;
+                let available_width = ui.available_width() - std_button_size.x * 5.0 - 48.0;
 
-                ui.add_space(available_width);
+                ui.add_space(available_width.max(0.0));
 
-                let button = egui::Button::new("Previous").min_size(std_button_size);
+                let button = egui::Button::new("Tag counts").min_size(std_button_size);
                 if ui.add(button).clicked() {
-                    self.can_open_warinig = true;
-
-                    let img_name = self.current_image.name();
-                    let img_caption = self.current_image.caption();
+                    self.tag_panel.open = !self.tag_panel.open;
+                }
 
-                    let img = self.img_loader.get_previous();
-                    if let Some(img) = img {
-                        self.current_image = img;
-                    }
+                // Removes stray separators, extra whitespace and duplicated tags
+                let button = egui::Button::new("Tidy").min_size(std_button_size);
+                if ui.add_enabled(!caption.is_prose(), button).clicked() {
+                    self.set_caption(caption.serialize(&format));
+                }
 
-                    self.img_loader.save_caption(&img_name, &img_caption);
+                let button = egui::Button::new("Previous").min_size(std_button_size);
+                if ui.add(button).clicked() {
+                    self.previous();
                 }
 
                 let button = egui::Button::new("Next").min_size(std_button_size);
                 if ui.add(button).clicked() {
-                    self.can_open_warinig = true;
-                    let img = self.img_loader.get_next();
-
-                    let img_name = self.current_image.name();
-                    let img_caption = self.current_image.caption();
-
-                    if let Some(img) = img {
-                        self.current_image = img;
-                    }
-
-                    self.img_loader.save_caption(&img_name, &img_caption);
+                    self.next();
                 }
 
                 let button = egui::Button::new("Save").min_size(std_button_size);
                 if ui.add(button).clicked() {
-                    self.img_loader.save(&self.current_image);
+                    self.save_all();
                 }
             });
 
             ui.add_space(10.0);
+            // The grid takes all the space left, the persistent textarea is shown only with the single image
+            if self.view_mode == ViewMode::Grid {
+                self.show_grid(ui);
+                return;
+            }
+
             ui.horizontal(|ui| {
                 //Main pic
-                self.current_image.show(ui);
+                if self.crop_tool.active {
+                    self.show_crop_tool(ui);
+                } else {
+                    self.current_image.show(ui, egui::Vec2::splat(512.0));
+                }
+
+                ui.vertical(|ui| {
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(&mut self.editor_mode, EditorMode::Raw, "Raw text");
+                        ui.selectable_value(&mut self.editor_mode, EditorMode::Tags, "Tags");
+                        ui.separator();
+
+                        let name = self.current_image.name();
+                        let caption = &self.current_image.caption;
+                        let can_undo = self.history.can_undo(&name, caption);
+                        let can_redo = self.history.can_redo(&name, caption);
+                        let edited = self.history.opened(&name) != Some(caption.as_str());
+                        if ui
+                            .add_enabled(can_undo, egui::Button::new("Undo"))
+                            .clicked()
+                        {
+                            self.undo();
+                        }
+                        if ui
+                            .add_enabled(can_redo, egui::Button::new("Redo"))
+                            .clicked()
+                        {
+                            self.redo();
+                        }
+                        let revert = egui::Button::new("Revert");
+                        if ui
+                            .add_enabled(edited, revert)
+                            .on_hover_text("Caption it had when the folder was opened")
+                            .clicked()
+                        {
+                            self.revert();
+                        }
+                    });
+
+                    // If the tag is very long, I dont want the textbox take alla the space
+                    egui::ScrollArea::vertical().show(ui, |ui| match self.editor_mode {
+                        EditorMode::Raw => {
+                            let format = self.options.caption_format;
+                            let text_edit_id = egui::Id::new("caption_text_edit");
+                            let name = self.current_image.name();
+                            self.autocomplete.show(
+                                ui,
+                                text_edit_id,
+                                &mut self.current_image.caption,
+                                &name,
+                                &self.vocabulary,
+                                &format,
+                            );
 
-                // If the tag is very long, I dont want the textbox take alla the space
-                egui::ScrollArea::vertical().show(ui, |ui| {
-                    // Tags textbox
-                    let text_edit_multiline =
-                        egui::TextEdit::multiline(&mut self.current_image.caption)
-                            .desired_width(f32::INFINITY)
-                            .desired_rows(self.desired_rows);
+                            // Tags textbox
+                            let text_edit_multiline =
+                                egui::TextEdit::multiline(&mut self.current_image.caption)
+                                    .id(text_edit_id)
+                                    .desired_width(f32::INFINITY)
+                                    .desired_rows(self.desired_rows);
 
-                    ui.add(text_edit_multiline);
+                            let output = text_edit_multiline.show(ui);
+                            self.autocomplete.update(
+                                &output,
+                                &self.current_image.caption,
+                                &name,
+                                &format,
+                            );
+                        }
+                        EditorMode::Tags => {
+                            let format = self.options.caption_format;
+                            self.tag_editor.show(
+                                ui,
+                                &mut self.current_image.caption,
+                                &self.vocabulary,
+                                &format,
+                            );
+                        }
+                    });
                 });
             });
 
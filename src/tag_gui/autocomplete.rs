@@ -0,0 +1,149 @@
+use ai_utils::caption::CaptionFormat;
+use ai_utils::vocabulary::Vocabulary;
+use egui::text::{CCursor, CCursorRange};
+use egui::text_edit::TextEditOutput;
+use egui::Id;
+
+const MAX_SUGGESTIONS: usize = 8;
+
+// The tag being typed, as a byte range of the caption
+struct Completion {
+    image: String,
+    start: usize,
+    end: usize,
+    prefix: String,
+}
+
+impl Completion {
+    // The caption could have been changed since the completion was computed
+    fn fits(&self, caption: &str) -> bool {
+        self.end <= caption.len()
+            && caption.is_char_boundary(self.start)
+            && caption.is_char_boundary(self.end)
+    }
+}
+
+// Suggests tags from the vocabulary while typing in the caption textbox
+pub struct Autocomplete {
+    completion: Option<Completion>,
+}
+
+impl Autocomplete {
+    pub fn new() -> Self {
+        Self { completion: None }
+    }
+
+    // Shows the suggestions for the tag under the cursor, clicking one replaces the tag in the caption
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        text_edit_id: Id,
+        caption: &mut String,
+        image: &str,
+        vocabulary: &Vocabulary,
+        format: &CaptionFormat,
+    ) {
+        // The completion is kept after the textbox loses focus, clicking a suggestion takes the focus away
+        let completion = match &self.completion {
+            Some(completion) if completion.image == image && completion.fits(caption) => completion,
+            _ => return,
+        };
+
+        let mut chosen = None;
+        ui.horizontal_wrapped(|ui| {
+            for (tag, count) in vocabulary.suggest(&completion.prefix, MAX_SUGGESTIONS) {
+                // Tags never used in the dataset are shown dimmed
+                let mut text = egui::RichText::new(format!("{} ({})", tag, count));
+                if vocabulary.is_imported(&tag) {
+                    text = text.weak();
+                }
+                if ui.small_button(text).clicked() {
+                    chosen = Some(tag);
+                }
+            }
+        });
+
+        if let Some(tag) = chosen {
+            let cursor = insert_tag(caption, completion.start, completion.end, &tag, format);
+            self.completion = None;
+
+            let ctx = ui.ctx();
+            let mut state = egui::TextEdit::load_state(ctx, text_edit_id).unwrap_or_default();
+            state.set_ccursor_range(Some(CCursorRange::one(CCursor::new(cursor))));
+            egui::TextEdit::store_state(ctx, text_edit_id, state);
+            ctx.memory_mut(|mem| mem.request_focus(text_edit_id));
+        }
+    }
+
+    // Finds the tag under the cursor of the textbox
+    pub fn update(
+        &mut self,
+        output: &TextEditOutput,
+        caption: &str,
+        image: &str,
+        format: &CaptionFormat,
+    ) {
+        if !output.response.has_focus() {
+            return;
+        }
+        let cursor = match output.cursor_range {
+            Some(range) => char_to_byte(caption, range.primary.ccursor.index),
+            None => return,
+        };
+
+        let is_end = |c: char| c == format.separator || c == '\n';
+        let start = caption[..cursor]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| is_end(*c))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let end = caption[cursor..]
+            .find(is_end)
+            .map_or(caption.len(), |i| cursor + i);
+        let prefix = caption[start..cursor].trim_start().trim_start_matches('(');
+
+        self.completion = if prefix.is_empty() {
+            None
+        } else {
+            Some(Completion {
+                image: image.to_string(),
+                start,
+                end,
+                prefix: prefix.to_string(),
+            })
+        };
+    }
+}
+
+// Replaces the caption between start and end with the tag, returns the cursor position after it in chars
+fn insert_tag(
+    caption: &mut String,
+    start: usize,
+    end: usize,
+    tag: &str,
+    format: &CaptionFormat,
+) -> usize {
+    let leading = if start == 0 || format.separator.is_whitespace() {
+        ""
+    } else {
+        " "
+    };
+    let rest = &caption[end..];
+    let trailing = if rest.trim_start().starts_with(format.separator) {
+        String::new()
+    } else {
+        // Separator is added so the next tag can be typed right away
+        format.joiner()
+    };
+    let inserted = format!("{}{}{}", leading, tag, trailing);
+    let new_caption = format!("{}{}{}", &caption[..start], inserted, rest);
+    let cursor = caption[..start].chars().count() + inserted.chars().count();
+    *caption = new_caption;
+    cursor
+}
+
+fn char_to_byte(text: &str, index: usize) -> usize {
+    text.char_indices()
+        .nth(index)
+        .map_or(text.len(), |(byte, _)| byte)
+}
@@ -0,0 +1,187 @@
+use ai_utils::buckets::{BucketOptions, BucketPlan};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+
+type Sizes = Vec<Option<(u32, u32)>>;
+
+// Window with the aspect ratio buckets of the images, the sizes of the images are read in
+// background the first time it is opened
+pub struct BucketPanel {
+    pub open: bool,
+    // Same order as the captions of the folder
+    paths: Vec<PathBuf>,
+    // The largest bucket is side x side
+    side: u32,
+    step: u32,
+    sizes: Option<Sizes>,
+    loading: Option<mpsc::Receiver<Sizes>>,
+    // Images that changed size while the sizes were read, the thread could have read them before
+    changed: Vec<(usize, (u32, u32))>,
+    plan: Option<BucketPlan>,
+    // Position of the bucket whose images are listed
+    selected: Option<usize>,
+}
+
+impl BucketPanel {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        Self {
+            open: false,
+            paths,
+            side: 1024,
+            step: 64,
+            sizes: None,
+            loading: None,
+            changed: Vec::new(),
+            plan: None,
+            selected: None,
+        }
+    }
+
+    fn options(&self) -> BucketOptions {
+        BucketOptions {
+            area: self.side as u64 * self.side as u64,
+            step: self.step,
+            ..Default::default()
+        }
+    }
+
+    fn load_sizes(&mut self, ctx: &egui::Context) {
+        if let Some(loading) = &self.loading {
+            if let Ok(mut sizes) = loading.try_recv() {
+                for (position, size) in self.changed.drain(..) {
+                    sizes[position] = Some(size);
+                }
+                self.plan = Some(BucketPlan::new(&sizes, &self.options()));
+                self.sizes = Some(sizes);
+                self.loading = None;
+            }
+            return;
+        }
+        if self.sizes.is_some() {
+            return;
+        }
+
+        let (send, recv) = mpsc::channel();
+        let paths = self.paths.clone();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let sizes: Sizes = paths
+                .iter()
+                .map(|path| image::image_dimensions(path).ok())
+                .collect();
+            let _ = send.send(sizes);
+            ctx.request_repaint();
+        });
+        self.loading = Some(recv);
+    }
+
+    // The image at the position changed size
+    pub fn image_changed(&mut self, position: usize, size: (u32, u32)) {
+        let options = self.options();
+        if let Some(sizes) = &mut self.sizes {
+            sizes[position] = Some(size);
+            self.plan = Some(BucketPlan::new(sizes, &options));
+        } else if self.loading.is_some() {
+            self.changed.push((position, size));
+        }
+    }
+
+    // Returns the image to open, clicking an image of a bucket opens it
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        captions: &[(String, String)],
+        current: &str,
+    ) -> Option<String> {
+        if !self.open {
+            return None;
+        }
+        self.load_sizes(ctx);
+
+        let mut open = self.open;
+        let mut jump = None;
+        egui::Window::new("Buckets")
+            .open(&mut open)
+            .default_height(400.0)
+            .show(ctx, |ui| {
+                let mut changed = false;
+                ui.horizontal(|ui| {
+                    ui.label("Largest bucket");
+                    let side = egui::DragValue::new(&mut self.side)
+                        .clamp_range(256..=4096)
+                        .speed(8.0);
+                    changed |= ui.add(side).changed();
+                    ui.label("Step");
+                    let step = egui::DragValue::new(&mut self.step).clamp_range(8..=256);
+                    changed |= ui.add(step).changed();
+                });
+                if changed {
+                    if let Some(sizes) = &self.sizes {
+                        self.plan = Some(BucketPlan::new(sizes, &self.options()));
+                        self.selected = None;
+                    }
+                }
+
+                let (plan, sizes) = match (&self.plan, &self.sizes) {
+                    (Some(plan), Some(sizes)) => (plan, sizes),
+                    _ => {
+                        ui.horizontal(|ui| {
+                            ui.spinner();
+                            ui.label("Reading the image sizes");
+                        });
+                        return;
+                    }
+                };
+
+                if let Some(i) = captions.iter().position(|(name, _)| name == current) {
+                    match (sizes[i], plan.assignments[i]) {
+                        (Some((width, height)), Some(assignment)) => ui.label(format!(
+                            "{}: {}x{} -> {}x{}, {:.1}% cropped",
+                            current,
+                            width,
+                            height,
+                            assignment.bucket.0,
+                            assignment.bucket.1,
+                            assignment.crop_loss * 100.0
+                        )),
+                        _ => ui.label(format!("{} can't be read", current)),
+                    };
+                }
+                ui.separator();
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    egui::Grid::new("buckets").striped(true).show(ui, |ui| {
+                        for (position, population) in plan.populations.iter().enumerate() {
+                            let (width, height) = population.bucket;
+                            let selected = self.selected == Some(position);
+                            let label = format!("{}x{}", width, height);
+                            if ui.selectable_label(selected, label).clicked() {
+                                self.selected = (!selected).then_some(position);
+                            }
+                            ui.label(format!("{} images", population.images.len()));
+                            ui.weak(format!("{:.1}% cropped", population.mean_crop_loss * 100.0));
+                            ui.end_row();
+
+                            if !selected {
+                                continue;
+                            }
+                            for &i in &population.images {
+                                let name = &captions[i].0;
+                                ui.label("");
+                                if ui.link(name).clicked() {
+                                    jump = Some(name.clone());
+                                }
+                                if let Some(assignment) = plan.assignments[i] {
+                                    ui.weak(format!("{:.1}%", assignment.crop_loss * 100.0));
+                                }
+                                ui.end_row();
+                            }
+                        }
+                    });
+                });
+            });
+        self.open = open;
+        jump
+    }
+}
@@ -0,0 +1,156 @@
+use ai_utils::caption_edit::{EditOutcome, FileEdit, TagOperation};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OperationKind {
+    AddStart,
+    AddEnd,
+    Remove,
+    Replace,
+    MoveToFront,
+    Sort,
+    Dedup,
+}
+
+impl OperationKind {
+    const ALL: [OperationKind; 7] = [
+        OperationKind::AddStart,
+        OperationKind::AddEnd,
+        OperationKind::Remove,
+        OperationKind::Replace,
+        OperationKind::MoveToFront,
+        OperationKind::Sort,
+        OperationKind::Dedup,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            OperationKind::AddStart => "Add at start",
+            OperationKind::AddEnd => "Add at end",
+            OperationKind::Remove => "Remove",
+            OperationKind::Replace => "Replace",
+            OperationKind::MoveToFront => "Move to front",
+            OperationKind::Sort => "Sort",
+            OperationKind::Dedup => "Remove duplicates",
+        }
+    }
+
+    fn tags(&self) -> usize {
+        match self {
+            OperationKind::Replace => 2,
+            OperationKind::Sort | OperationKind::Dedup => 0,
+            _ => 1,
+        }
+    }
+}
+
+// Bar to apply a tag operation to every selected image, the result of the last one is shown per file
+pub struct BulkEdit {
+    kind: OperationKind,
+    tag: String,
+    new_tag: String,
+    pub error: Option<String>,
+    pub report: Option<Vec<FileEdit>>,
+}
+
+impl BulkEdit {
+    pub fn new() -> Self {
+        Self {
+            kind: OperationKind::AddEnd,
+            tag: String::new(),
+            new_tag: String::new(),
+            error: None,
+            report: None,
+        }
+    }
+
+    // Returns the operation to apply when the button is clicked
+    pub fn show(&mut self, ui: &mut egui::Ui, selected: usize) -> Option<TagOperation> {
+        let mut operation = None;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("bulk_operation")
+                .selected_text(self.kind.label())
+                .show_ui(ui, |ui| {
+                    for kind in OperationKind::ALL {
+                        ui.selectable_value(&mut self.kind, kind, kind.label());
+                    }
+                });
+
+            if self.kind.tags() > 0 {
+                let hint = if self.kind == OperationKind::Replace {
+                    "Old tag"
+                } else {
+                    "Tag"
+                };
+                ui.add(egui::TextEdit::singleline(&mut self.tag).hint_text(hint));
+            }
+            if self.kind.tags() > 1 {
+                ui.add(egui::TextEdit::singleline(&mut self.new_tag).hint_text("New tag"));
+            }
+
+            let button = egui::Button::new(format!("Apply to {} images", selected));
+            if ui.add_enabled(selected > 0, button).clicked() {
+                operation = Some(self.operation());
+            }
+
+            if let Some(error) = &self.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+        });
+        operation
+    }
+
+    fn operation(&self) -> TagOperation {
+        let tag = self.tag.clone();
+        match self.kind {
+            OperationKind::AddStart => TagOperation::AddStart(tag),
+            OperationKind::AddEnd => TagOperation::AddEnd(tag),
+            OperationKind::Remove => TagOperation::Remove(tag),
+            OperationKind::Replace => TagOperation::Replace(tag, self.new_tag.clone()),
+            OperationKind::MoveToFront => TagOperation::MoveToFront(tag),
+            OperationKind::Sort => TagOperation::Sort,
+            OperationKind::Dedup => TagOperation::Dedup,
+        }
+    }
+
+    pub fn show_report(&mut self, ctx: &egui::Context) {
+        let report = match &self.report {
+            Some(report) => report,
+            None => return,
+        };
+
+        let mut open = true;
+        egui::Window::new("Bulk edit")
+            .open(&mut open)
+            .default_height(400.0)
+            .show(ctx, |ui| {
+                let changed = report
+                    .iter()
+                    .filter(|file| matches!(file.outcome, EditOutcome::Changed(_)))
+                    .count();
+                ui.label(format!("{} of {} captions modified", changed, report.len()));
+                ui.separator();
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    egui::Grid::new("bulk_report").striped(true).show(ui, |ui| {
+                        for file in report {
+                            ui.label(&file.name);
+                            match &file.outcome {
+                                EditOutcome::Changed(caption) => {
+                                    ui.label(caption);
+                                }
+                                EditOutcome::Unchanged => {
+                                    ui.weak("unchanged");
+                                }
+                                EditOutcome::Prose => {
+                                    ui.weak("skipped, prose caption");
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
+        if !open {
+            self.report = None;
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use ai_utils::buckets::assign;
+use ai_utils::crop::{CropRecord, CropRect};
+use ai_utils::resolution::Resolution;
+use egui::{Color32, CursorIcon, Pos2, Rect, Sense, Stroke, Vec2};
+
+use super::image_loader::CaptionedImg;
+
+// Size in points of the handles on the corners, dragging them resizes the rectangle
+const HANDLE_SIZE: f32 = 8.0;
+// Smallest width of the rectangle, in pixels of the image
+const MIN_SIDE: f32 = 16.0;
+
+// An aspect ratio the rectangle can be locked to
+struct Target {
+    label: String,
+    aspect: (u32, u32),
+    // Buckets and exact resolutions also have the size the crop is scaled to
+    size: Option<(u32, u32)>,
+}
+
+impl Target {
+    fn sized(width: u32, height: u32) -> Self {
+        Self {
+            label: format!("{}x{}", width, height),
+            aspect: (width, height),
+            size: Some((width, height)),
+        }
+    }
+
+    fn ratio(width: u32, height: u32) -> Self {
+        Self {
+            label: format!("{}:{}", width, height),
+            aspect: (width, height),
+            size: None,
+        }
+    }
+}
+
+enum Drag {
+    // Offset of the pointer from the top left corner
+    Move(Vec2),
+    // The corner that stays still while the opposite one follows the pointer
+    Resize(Pos2),
+}
+
+pub enum CropEvent {
+    Save(CropRecord),
+    Cancel,
+}
+
+// Crop mode over the current image: a rectangle locked to the aspect ratio of a bucket, moved by
+// dragging it and resized by dragging a corner. Dragging outside of it draws a new one.
+// The rectangle is in pixels of the image, not of the screen
+pub struct CropTool {
+    pub active: bool,
+    targets: Vec<Target>,
+    target: usize,
+    scale_to_size: bool,
+    // Name and size of the image the rectangle is on
+    image: Option<(String, (u32, u32))>,
+    rect: Rect,
+    drag: Option<Drag>,
+    // Outcome of the last save, shown under the buttons
+    pub message: Option<Result<String, String>>,
+}
+
+impl CropTool {
+    // The rectangle can have the aspect ratio of the expected sizes, or be a square
+    pub fn new(resolution: &Resolution) -> Self {
+        let mut targets: Vec<Target> = match resolution {
+            Resolution::Exact(width, height) => vec![Target::sized(*width, *height)],
+            Resolution::Buckets(sizes) => {
+                sizes.iter().map(|(w, h)| Target::sized(*w, *h)).collect()
+            }
+            Resolution::AspectRatios(ratios) => {
+                ratios.iter().map(|(w, h)| Target::ratio(*w, *h)).collect()
+            }
+            Resolution::Minimum(..) => Vec::new(),
+        };
+        if !targets
+            .iter()
+            .any(|target| target.aspect.0 == target.aspect.1)
+        {
+            targets.push(Target::ratio(1, 1));
+        }
+
+        Self {
+            active: false,
+            targets,
+            target: 0,
+            scale_to_size: true,
+            image: None,
+            rect: Rect::NOTHING,
+            drag: None,
+            message: None,
+        }
+    }
+
+    fn aspect(&self) -> f32 {
+        let (width, height) = self.targets[self.target].aspect;
+        width as f32 / height as f32
+    }
+
+    fn image_size(&self) -> Vec2 {
+        let (_, (width, height)) = self.image.clone().unwrap_or_default();
+        Vec2::new(width as f32, height as f32)
+    }
+
+    // A new image starts with the target closest to its aspect ratio
+    fn reset(&mut self, name: String, size: (u32, u32)) {
+        let aspects: Vec<(u32, u32)> = self.targets.iter().map(|target| target.aspect).collect();
+        self.target = assign(size, &aspects)
+            .and_then(|assignment| aspects.iter().position(|a| *a == assignment.bucket))
+            .unwrap_or(0);
+        if self.image.as_ref().is_none_or(|(old, _)| *old != name) {
+            self.message = None;
+        }
+        self.image = Some((name, size));
+        self.drag = None;
+        self.fit();
+    }
+
+    // The biggest rectangle that fits in the image, in the center
+    fn fit(&mut self) {
+        let (_, size) = self.image.clone().unwrap_or_default();
+        let rect = CropRect::centered(size, self.targets[self.target].aspect);
+        let min = Pos2::new(rect.x as f32, rect.y as f32);
+        self.rect = Rect::from_min_size(min, Vec2::new(rect.width as f32, rect.height as f32));
+    }
+
+    fn start_drag(&mut self, pos: Pos2, handle_size: f32) {
+        let rect = self.rect;
+        let corners = [
+            rect.left_top(),
+            rect.right_top(),
+            rect.left_bottom(),
+            rect.right_bottom(),
+        ];
+        let corner = corners
+            .iter()
+            .position(|corner| corner.distance(pos) <= handle_size);
+        self.drag = Some(match corner {
+            // The corners are listed so that the opposite one is at the mirrored position
+            Some(i) => Drag::Resize(corners[3 - i]),
+            None if rect.contains(pos) => Drag::Move(pos - rect.min),
+            None => Drag::Resize(pos),
+        });
+    }
+
+    fn drag_to(&mut self, pos: Pos2) {
+        let image_size = self.image_size();
+        match self.drag {
+            Some(Drag::Move(offset)) => {
+                let max = (image_size - self.rect.size()).to_pos2();
+                let min = (pos - offset).clamp(Pos2::ZERO, max);
+                self.rect = Rect::from_min_size(min, self.rect.size());
+            }
+            Some(Drag::Resize(anchor)) => {
+                let aspect = self.aspect();
+                let delta = pos - anchor;
+                // Space between the anchor and the border of the image on the side of the pointer
+                let room_x = if delta.x >= 0.0 {
+                    image_size.x - anchor.x
+                } else {
+                    anchor.x
+                };
+                let room_y = if delta.y >= 0.0 {
+                    image_size.y - anchor.y
+                } else {
+                    anchor.y
+                };
+                let width = delta
+                    .x
+                    .abs()
+                    .max(delta.y.abs() * aspect)
+                    .max(MIN_SIDE)
+                    .min(room_x)
+                    .min(room_y * aspect);
+                let size = Vec2::new(width, width / aspect);
+                let x = if delta.x >= 0.0 {
+                    anchor.x
+                } else {
+                    anchor.x - size.x
+                };
+                let y = if delta.y >= 0.0 {
+                    anchor.y
+                } else {
+                    anchor.y - size.y
+                };
+                self.rect = Rect::from_min_size(Pos2::new(x, y), size);
+            }
+            None => {}
+        }
+    }
+
+    fn crop_rect(&self) -> CropRect {
+        let (_, (width, height)) = self.image.clone().unwrap_or_default();
+        let x = (self.rect.min.x.round() as u32).min(width.saturating_sub(1));
+        let y = (self.rect.min.y.round() as u32).min(height.saturating_sub(1));
+        CropRect {
+            x,
+            y,
+            width: (self.rect.width().round() as u32).clamp(1, (width - x).max(1)),
+            height: (self.rect.height().round() as u32).clamp(1, (height - y).max(1)),
+        }
+    }
+
+    fn record(&self) -> CropRecord {
+        let (name, size) = self.image.clone().unwrap_or_default();
+        CropRecord {
+            image: name,
+            original: size,
+            rect: self.crop_rect(),
+            size: self.targets[self.target]
+                .size
+                .filter(|_| self.scale_to_size),
+        }
+    }
+
+    // Shows the image with the rectangle over it and the buttons to save the crop,
+    // the destination tells where the crop is written
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        img: &CaptionedImg,
+        max_size: Vec2,
+        destination: &str,
+    ) -> Option<CropEvent> {
+        let name = img.name();
+        let size = img.size();
+        if self.image.as_ref() != Some(&(name.clone(), size)) {
+            self.reset(name, size);
+        }
+
+        let image_size = self.image_size();
+        let scale = (max_size.x / image_size.x).min(max_size.y / image_size.y);
+        let (response, painter) = ui.allocate_painter(image_size * scale, Sense::drag());
+        let origin = response.rect.min;
+        let to_screen = |pos: Pos2| origin + pos.to_vec2() * scale;
+        let to_image = |pos: Pos2| {
+            let pos = ((pos - origin) / scale).to_pos2();
+            pos.clamp(Pos2::ZERO, image_size.to_pos2())
+        };
+
+        if response.drag_started() {
+            if let Some(pressed) = ui.input(|i| i.pointer.press_origin()) {
+                self.start_drag(to_image(pressed), HANDLE_SIZE / scale);
+            }
+        }
+        if let Some(pointer) = response.interact_pointer_pos() {
+            self.drag_to(to_image(pointer));
+        }
+        if response.drag_released() {
+            self.drag = None;
+        }
+        if let Some(hovered) = response.hover_pos() {
+            if self.rect.contains(to_image(hovered)) {
+                ui.ctx().set_cursor_icon(CursorIcon::Move);
+            }
+        }
+
+        let uv = Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+        painter.image(img.texture_id(ui.ctx()), response.rect, uv, Color32::WHITE);
+        let full = response.rect;
+        let crop = Rect::from_min_max(to_screen(self.rect.min), to_screen(self.rect.max));
+        // The parts cut away are darkened
+        let shade = Color32::from_black_alpha(160);
+        let cut = [
+            Rect::from_min_max(full.min, Pos2::new(full.max.x, crop.min.y)),
+            Rect::from_min_max(Pos2::new(full.min.x, crop.max.y), full.max),
+            Rect::from_min_max(
+                Pos2::new(full.min.x, crop.min.y),
+                Pos2::new(crop.min.x, crop.max.y),
+            ),
+            Rect::from_min_max(
+                Pos2::new(crop.max.x, crop.min.y),
+                Pos2::new(full.max.x, crop.max.y),
+            ),
+        ];
+        for part in cut {
+            painter.rect_filled(part, 0.0, shade);
+        }
+        painter.rect_stroke(crop, 0.0, Stroke::new(1.5, Color32::WHITE));
+        for corner in [
+            crop.left_top(),
+            crop.right_top(),
+            crop.left_bottom(),
+            crop.right_bottom(),
+        ] {
+            let handle = Rect::from_center_size(corner, Vec2::splat(HANDLE_SIZE));
+            painter.rect_filled(handle, 0.0, Color32::WHITE);
+        }
+
+        let mut event = None;
+        ui.horizontal(|ui| {
+            let mut target = self.target;
+            egui::ComboBox::from_id_source("crop_target")
+                .selected_text(&self.targets[target].label)
+                .show_ui(ui, |ui| {
+                    for (i, option) in self.targets.iter().enumerate() {
+                        ui.selectable_value(&mut target, i, &option.label);
+                    }
+                });
+            if target != self.target {
+                self.target = target;
+                self.fit();
+            }
+            if let Some((width, height)) = self.targets[self.target].size {
+                let label = format!("Scale to {}x{}", width, height);
+                ui.checkbox(&mut self.scale_to_size, label);
+            }
+            if ui.button("Reset").clicked() {
+                self.fit();
+            }
+        });
+
+        let record = self.record();
+        ui.horizontal(|ui| {
+            let CropRect {
+                x,
+                y,
+                width,
+                height,
+            } = record.rect;
+            ui.label(format!("{}x{} at {},{}", width, height, x, y));
+            if let Some((out_width, out_height)) = record.size {
+                if width < out_width || height < out_height {
+                    ui.colored_label(ui.visuals().warn_fg_color, "enlarged")
+                        .on_hover_text("The crop is smaller than the size it is scaled to");
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Save crop").clicked() {
+                event = Some(CropEvent::Save(record));
+            }
+            if ui.button("Cancel").clicked() {
+                event = Some(CropEvent::Cancel);
+            }
+            ui.weak(destination);
+        });
+        match &self.message {
+            Some(Ok(message)) => {
+                ui.weak(message);
+            }
+            Some(Err(e)) => {
+                ui.colored_label(ui.visuals().error_fg_color, e);
+            }
+            None => {}
+        }
+        event
+    }
+}
@@ -0,0 +1,267 @@
+use ai_utils::caption::{Caption, CaptionFormat};
+use ai_utils::resolution::Resolution;
+use egui::{Align2, Color32, FontId, Pos2, Rect, Sense, Stroke, Vec2};
+use std::collections::{BTreeSet, HashSet};
+use std::path::PathBuf;
+
+use super::thumbnails::{Thumbnail, Thumbnails, THUMBNAIL_SIZE};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViewMode {
+    Single,
+    Grid,
+}
+
+const PADDING: f32 = 8.0;
+const TEXT_HEIGHT: f32 = 36.0;
+// Roughly how many characters of the small font fit under a thumbnail
+const LINE_CHARS: usize = 20;
+
+// Shows every image of the folder as a thumbnail with the start of its caption.
+// Click selects an image, ctrl+click adds or removes one and shift+click selects a range
+pub struct GridView {
+    // Same order as the captions of the folder
+    paths: Vec<PathBuf>,
+    // Created with the first frame, the thumbnail threads need the egui context
+    thumbnails: Option<Thumbnails>,
+    // Positions in the folder of the selected images
+    selected: BTreeSet<usize>,
+    // Start of the range selected with shift
+    anchor: Option<usize>,
+}
+
+impl GridView {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        Self {
+            paths,
+            thumbnails: None,
+            selected: BTreeSet::new(),
+            anchor: None,
+        }
+    }
+
+    pub fn selected_names(&self, captions: &[(String, String)]) -> HashSet<String> {
+        self.selected
+            .iter()
+            .map(|index| captions[*index].0.clone())
+            .collect()
+    }
+
+    pub fn selected_count(&self) -> usize {
+        self.selected.len()
+    }
+
+    pub fn select_all(&mut self, captions: &[(String, String)], filter: Option<&HashSet<String>>) {
+        self.selected = visible(captions, filter).into_iter().collect();
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selected.clear();
+        self.anchor = None;
+    }
+
+    // The image at the position changed on disk
+    pub fn image_changed(&mut self, position: usize) {
+        if let Some(thumbnails) = &mut self.thumbnails {
+            thumbnails.forget(&self.paths[position]);
+        }
+    }
+
+    // Returns the image to open, double clicking a thumbnail opens it
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        captions: &[(String, String)],
+        filter: Option<&HashSet<String>>,
+        current: &str,
+        format: &CaptionFormat,
+        resolution: &Resolution,
+    ) -> Option<String> {
+        let thumbnails = self
+            .thumbnails
+            .get_or_insert_with(|| Thumbnails::new(ui.ctx()));
+        let visible = visible(captions, filter);
+
+        let side = THUMBNAIL_SIZE as f32;
+        let cell = Vec2::new(side + PADDING, side + TEXT_HEIGHT + PADDING);
+        let columns =
+            ((ui.available_width() / (cell.x + ui.spacing().item_spacing.x)) as usize).max(1);
+        let rows = visible.len().div_ceil(columns);
+
+        let mut clicked = None;
+        let mut open = None;
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show_rows(ui, cell.y, rows, |ui, rows| {
+                for row in rows {
+                    ui.horizontal(|ui| {
+                        for &index in visible.iter().skip(row * columns).take(columns) {
+                            let (rect, response) = ui.allocate_exact_size(cell, Sense::click());
+                            if !ui.is_rect_visible(rect) {
+                                continue;
+                            }
+                            let (name, caption) = &captions[index];
+                            let thumbnail = thumbnails.get(&self.paths[index]);
+                            let selected = self.selected.contains(&index);
+                            let cell = Cell {
+                                name,
+                                caption,
+                                thumbnail,
+                                selected,
+                            };
+                            let size_check = draw_cell(ui, rect, &cell, format, resolution);
+                            let response = match size_check {
+                                Err(reason) => response.on_hover_text(reason),
+                                Ok(()) => response,
+                            };
+                            if name == current {
+                                let stroke = Stroke::new(2.0, ui.visuals().hyperlink_color);
+                                ui.painter().rect_stroke(rect, 4.0, stroke);
+                            }
+
+                            if response.double_clicked() {
+                                open = Some(name.clone());
+                            } else if response.clicked() {
+                                clicked = Some(index);
+                            }
+                        }
+                    });
+                }
+            });
+
+        if let Some(index) = clicked {
+            let modifiers = ui.input(|i| i.modifiers);
+            self.click(index, &visible, modifiers);
+        }
+        open
+    }
+
+    fn click(&mut self, index: usize, visible: &[usize], modifiers: egui::Modifiers) {
+        if modifiers.shift {
+            let anchor = self.anchor.unwrap_or(index);
+            let from = visible.iter().position(|i| *i == anchor).unwrap_or(0);
+            let to = visible.iter().position(|i| *i == index).unwrap_or(0);
+            if !modifiers.command {
+                self.selected.clear();
+            }
+            self.selected.extend(&visible[from.min(to)..=from.max(to)]);
+        } else if modifiers.command {
+            if !self.selected.remove(&index) {
+                self.selected.insert(index);
+            }
+            self.anchor = Some(index);
+        } else {
+            self.selected.clear();
+            self.selected.insert(index);
+            self.anchor = Some(index);
+        }
+    }
+}
+
+// Positions of the images matching the filter
+fn visible(captions: &[(String, String)], filter: Option<&HashSet<String>>) -> Vec<usize> {
+    (0..captions.len())
+        .filter(|i| filter.is_none_or(|filter| filter.contains(&captions[*i].0)))
+        .collect()
+}
+
+struct Cell<'a> {
+    name: &'a str,
+    caption: &'a str,
+    thumbnail: &'a Thumbnail,
+    selected: bool,
+}
+
+// Returns why the size of the image is wrong
+fn draw_cell(
+    ui: &egui::Ui,
+    rect: Rect,
+    cell: &Cell,
+    format: &CaptionFormat,
+    resolution: &Resolution,
+) -> Result<(), String> {
+    let Cell {
+        name,
+        caption,
+        thumbnail,
+        selected,
+    } = *cell;
+    let painter = ui.painter();
+    let visuals = ui.visuals();
+    let mut size_check = Ok(());
+    if selected {
+        painter.rect_filled(rect, 4.0, visuals.selection.bg_fill);
+    }
+
+    let side = THUMBNAIL_SIZE as f32;
+    let area = Rect::from_min_size(rect.min + Vec2::splat(PADDING / 2.0), Vec2::splat(side));
+    let mut badges = Vec::new();
+    match thumbnail {
+        Thumbnail::Loading => {
+            painter.text(
+                area.center(),
+                Align2::CENTER_CENTER,
+                "...",
+                FontId::proportional(14.0),
+                visuals.weak_text_color(),
+            );
+        }
+        Thumbnail::Failed => badges.push("invalid"),
+        Thumbnail::Ready(texture, [width, height]) => {
+            let image = Rect::from_center_size(area.center(), texture.size_vec2());
+            let uv = Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+            painter.image(texture.id(), image, uv, Color32::WHITE);
+            size_check = resolution.check(*width, *height);
+            if size_check.is_err() {
+                badges.push("size");
+            }
+        }
+    }
+
+    if caption.trim().is_empty() {
+        badges.push("no caption");
+    } else if Caption::parse(caption, format).is_prose() {
+        badges.push("prose");
+    }
+
+    // Badges in the top left corner of the thumbnail
+    let mut pos = area.min + Vec2::splat(2.0);
+    for badge in badges {
+        let galley = painter.layout_no_wrap(
+            badge.to_string(),
+            FontId::proportional(11.0),
+            Color32::WHITE,
+        );
+        let badge_rect = Rect::from_min_size(pos, galley.size()).expand(2.0);
+        painter.rect_filled(badge_rect, 2.0, visuals.error_fg_color);
+        pos.x += badge_rect.width() + 4.0;
+        painter.galley(badge_rect.min + Vec2::splat(2.0), galley);
+    }
+
+    let text_pos = Pos2::new(rect.center().x, area.max.y + 2.0);
+    painter.text(
+        text_pos,
+        Align2::CENTER_TOP,
+        shorten(name),
+        FontId::proportional(12.0),
+        visuals.text_color(),
+    );
+    let snippet_pos = text_pos + Vec2::new(0.0, 16.0);
+    painter.text(
+        snippet_pos,
+        Align2::CENTER_TOP,
+        shorten(caption.trim()),
+        FontId::proportional(11.0),
+        visuals.weak_text_color(),
+    );
+    size_check
+}
+
+fn shorten(text: &str) -> String {
+    let line = text.lines().next().unwrap_or_default();
+    if line.chars().count() <= LINE_CHARS {
+        return line.to_string();
+    }
+    let short: String = line.chars().take(LINE_CHARS - 1).collect();
+    format!("{}…", short)
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+// Steps kept for every image, the oldest ones are dropped
+const MAX_STEPS: usize = 200;
+
+struct ImageHistory {
+    undo: Vec<String>,
+    redo: Vec<String>,
+    // The last recorded caption
+    last: String,
+}
+
+// Undo and redo of the captions of every image, kept for the whole session so moving to another
+// image and back does not lose them
+pub struct History {
+    images: HashMap<String, ImageHistory>,
+    // Captions as they were when the folder was opened
+    opened: HashMap<String, String>,
+}
+
+impl History {
+    pub fn new(captions: &[(String, String)]) -> Self {
+        Self {
+            images: HashMap::new(),
+            opened: captions.iter().cloned().collect(),
+        }
+    }
+
+    fn image(&mut self, name: &str) -> &mut ImageHistory {
+        let opened = self.opened.get(name).cloned().unwrap_or_default();
+        self.images
+            .entry(name.to_string())
+            .or_insert_with(|| ImageHistory {
+                undo: Vec::new(),
+                redo: Vec::new(),
+                last: opened,
+            })
+    }
+
+    pub fn is_recorded(&self, name: &str, caption: &str) -> bool {
+        match self.images.get(name) {
+            Some(image) => image.last == caption,
+            None => self
+                .opened
+                .get(name)
+                .is_some_and(|opened| opened == caption),
+        }
+    }
+
+    // Adds the caption as a new step if it changed since the last one
+    pub fn record(&mut self, name: &str, caption: &str) {
+        let image = self.image(name);
+        if image.last == caption {
+            return;
+        }
+        let previous = std::mem::replace(&mut image.last, caption.to_string());
+        image.undo.push(previous);
+        if image.undo.len() > MAX_STEPS {
+            image.undo.remove(0);
+        }
+        image.redo.clear();
+    }
+
+    // The caption is the one being edited, it is recorded first so redo can go back to it
+    pub fn undo(&mut self, name: &str, caption: &str) -> Option<String> {
+        self.record(name, caption);
+        let image = self.image(name);
+        let previous = image.undo.pop()?;
+        let current = std::mem::replace(&mut image.last, previous.clone());
+        image.redo.push(current);
+        Some(previous)
+    }
+
+    pub fn redo(&mut self, name: &str, caption: &str) -> Option<String> {
+        // Editing after an undo starts a new branch, the redo steps are gone
+        self.record(name, caption);
+        let image = self.image(name);
+        let next = image.redo.pop()?;
+        let current = std::mem::replace(&mut image.last, next.clone());
+        image.undo.push(current);
+        Some(next)
+    }
+
+    pub fn can_undo(&self, name: &str, caption: &str) -> bool {
+        !self.is_recorded(name, caption)
+            || self
+                .images
+                .get(name)
+                .is_some_and(|image| !image.undo.is_empty())
+    }
+
+    pub fn can_redo(&self, name: &str, caption: &str) -> bool {
+        self.is_recorded(name, caption)
+            && self
+                .images
+                .get(name)
+                .is_some_and(|image| !image.redo.is_empty())
+    }
+
+    pub fn opened(&self, name: &str) -> Option<&str> {
+        self.opened.get(name).map(String::as_str)
+    }
+}
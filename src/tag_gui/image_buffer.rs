@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::thread;
+
+use super::image_loader::CaptionedImg;
+
+const MB: usize = 1024 * 1024;
+
+// How many images are kept decoded around the one being shown
+#[derive(Clone, Copy, Debug)]
+pub struct BufferOptions {
+    // Images after the current one in the direction of movement
+    pub ahead: usize,
+    pub behind: usize,
+    // The images are evicted when the buffer uses more memory than this, in bytes
+    pub memory_budget: usize,
+    // Threads decoding the images in background
+    pub threads: usize,
+}
+
+impl BufferOptions {
+    pub fn new(images: usize, memory_mb: usize) -> Self {
+        Self {
+            ahead: images,
+            behind: images,
+            memory_budget: memory_mb * MB,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for BufferOptions {
+    fn default() -> Self {
+        let threads = thread::available_parallelism().map_or(2, |n| n.get());
+        Self {
+            ahead: 8,
+            behind: 8,
+            memory_budget: 1024 * MB,
+            threads: threads.clamp(1, 4),
+        }
+    }
+}
+
+// Decoded images by name, the worker thread decides which ones are worth keeping
+pub struct ImageBuffer {
+    options: BufferOptions,
+    images: HashMap<String, CaptionedImg>,
+    size: usize,
+}
+
+impl ImageBuffer {
+    pub fn new(options: BufferOptions) -> Self {
+        Self {
+            options,
+            images: HashMap::new(),
+            size: 0,
+        }
+    }
+
+    pub fn options(&self) -> &BufferOptions {
+        &self.options
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CaptionedImg> {
+        self.images.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.images.contains_key(name)
+    }
+
+    pub fn insert(&mut self, img: CaptionedImg) {
+        self.size += img.memory_size();
+        if let Some(old) = self.images.insert(img.name(), img) {
+            self.size -= old.memory_size();
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        if let Some(img) = self.images.remove(name) {
+            self.size -= img.memory_size();
+        }
+    }
+
+    // Keeps the buffered copy in sync with the caption saved by the gui
+    pub fn set_caption(&mut self, name: &str, caption: &str) {
+        if let Some(img) = self.images.get_mut(name) {
+            img.caption = caption.to_string();
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.size >= self.options.memory_budget
+    }
+
+    // The window has the images to keep, the most important first. Images outside of it are
+    // dropped, then the least important ones until the budget is respected, the first one is always kept
+    pub fn evict(&mut self, window: &[String]) {
+        self.images.retain(|name, _| window.contains(name));
+        self.size = self.images.values().map(|img| img.memory_size()).sum();
+
+        for name in window.iter().skip(1).rev() {
+            if self.size <= self.options.memory_budget {
+                break;
+            }
+            if let Some(img) = self.images.remove(name) {
+                self.size -= img.memory_size();
+            }
+        }
+    }
+}
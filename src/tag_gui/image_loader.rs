@@ -1,27 +1,46 @@
+use ai_utils::{Dataset, DatasetEntry};
 use egui;
 use egui::Vec2;
 use egui_extras::image::RetainedImage;
-use std::fs;
-use std::fs::File;
-use std::io::Read;
-use std::io::Write;
-use std::sync::mpsc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{mpsc, Arc, Mutex};
+
+use super::image_buffer::{BufferOptions, ImageBuffer};
 
 use std::thread;
 use std::thread::JoinHandle;
 
-#[allow(dead_code)]
 enum BufferCommand {
     LoadNext,
     LoadPrevious,
+    // Loads the image with the given name, the next commands move from there
+    LoadName(String),
+    LoadAt(usize),
+    // Only the images with these names are loaded, None goes back to all the images.
+    // The next LoadNext starts again from the first image
+    Filter(Option<HashSet<String>>),
+    // The commands sent before it are handled
     Stop,
-    Save(String, String),
+    // The image changed on disk, the buffered copy is dropped
+    Forget(String),
+    // The gui wrote the caption, the buffered copy is updated
+    Saved(String, String),
+    // Sent by the prefetch threads when they are done with an image
+    Prefetched(String, Prefetch),
+}
+
+enum Prefetch {
+    Loaded(Box<CaptionedImg>),
+    Failed,
+    // The image was not needed anymore when the thread got to it
+    Skipped,
 }
 
 enum BufferResult {
     None,
     Next(CaptionedImg),
     Previous(CaptionedImg),
+    At(CaptionedImg),
 }
 
 impl BufferResult {
@@ -29,6 +48,7 @@ impl BufferResult {
         match self {
             BufferResult::Next(img) => img,
             BufferResult::Previous(img) => img,
+            BufferResult::At(img) => img,
             BufferResult::None => panic!("BufferResult::None"),
         }
     }
@@ -46,14 +66,26 @@ impl BufferResult {
     }
 }
 
-//#[derive(Clone)]
+// Where the loader is in the list of images it can load
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Position {
+    pub index: usize,
+    pub len: usize,
+}
+
+impl Position {
+    fn new(index: usize, len: usize) -> Self {
+        Self { index, len }
+    }
+}
+
+// Cloning is cheap, the image is shared between the clones
+#[derive(Clone)]
 pub struct CaptionedImg {
     name: String,
     pub caption: String,
-    img: Vec<u8>,
-    //Retained images are not clonable
-    cache: RetainedImage,
-    wrong_size: bool,
+    img: Arc<Vec<u8>>,
+    cache: Arc<RetainedImage>,
 }
 
 impl CaptionedImg {
@@ -63,15 +95,25 @@ impl CaptionedImg {
     pub fn caption(&self) -> String {
         self.caption.clone()
     }
-    pub fn img(&self) -> Vec<u8> {
-        self.img.clone()
+    // Scaled to fit in the size, keeping the aspect ratio
+    pub fn show(&mut self, ui: &mut egui::Ui, max_size: Vec2) {
+        let size = Vec2::new(self.cache.width() as f32, self.cache.height() as f32);
+        let scale = (max_size.x / size.x).min(max_size.y / size.y);
+        self.cache.show_size(ui, size * scale);
     }
-    pub fn show(&mut self, ui: &mut egui::Ui) {
-        self.cache.show_size(ui, Vec2::from((512.0, 512.0)));
+
+    pub fn texture_id(&self, ctx: &egui::Context) -> egui::TextureId {
+        self.cache.texture_id(ctx)
+    }
+
+    // Width and height in pixels
+    pub fn size(&self) -> (u32, u32) {
+        (self.cache.width() as u32, self.cache.height() as u32)
     }
 
-    pub fn is_wrong_size(&self) -> bool {
-        self.wrong_size
+    // Bytes used by the file and the decoded image
+    pub fn memory_size(&self) -> usize {
+        self.img.len() + self.cache.width() * self.cache.height() * 4
     }
 
     pub fn new(name: &str, caption: &str, img: &[u8]) -> Option<Self> {
@@ -81,66 +123,38 @@ impl CaptionedImg {
             return None;
         }
         let cache = cache.unwrap();
-        let mut w_size = false;
-        if cache.width() != 512 || cache.height() != 512 {
-            println!(
-                "Imge {} has wrong size w:{} h:{}",
-                &name,
-                cache.width(),
-                cache.height()
-            );
-            w_size = true;
-        }
         Some(Self {
             name: name.to_string(),
             caption: caption.to_string(),
-            img: img.to_vec(),
-            cache,
-            wrong_size: w_size,
+            img: Arc::new(img.to_vec()),
+            cache: Arc::new(cache),
         })
     }
 }
 
-impl Clone for CaptionedImg {
-    fn clone(&self) -> Self {
-        Self {
-            name: self.name.clone(),
-            caption: self.caption.clone(),
-            img: self.img.clone(),
-            cache: egui_extras::RetainedImage::from_image_bytes(&self.name, &self.img).unwrap(),
-            wrong_size: self.wrong_size,
-        }
-    }
-
-    fn clone_from(&mut self, source: &Self) {
-        self.name = source.name();
-        self.caption = source.caption();
-        self.img = source.img();
-        self.cache = egui_extras::RetainedImage::from_image_bytes(&self.name, &self.img).unwrap();
-        self.wrong_size = source.is_wrong_size();
-    }
-}
-
 struct WorkerThreadData {
-    t_files: Vec<String>,
-    t_dir: String,
-    send_channel: mpsc::Sender<BufferResult>,
-    recv_channel: mpsc::Receiver<BufferCommand>,
+    t_files: Vec<DatasetEntry>,
+    buffer: BufferOptions,
+    send_channel: mpsc::Sender<(BufferResult, Position)>,
+    // Used by the prefetch threads to send back the images
+    prefetch_channel: mpsc::Sender<BufferCommand>,
+    failed: Arc<Mutex<HashSet<String>>>,
 }
 
 // This struct is used as a buffer for preloading the images,to speed up the loading
 pub struct ImageLoader {
-    _thread_handle: std::thread::JoinHandle<()>,
+    // None once the thread is stopped
+    thread_handle: Option<std::thread::JoinHandle<()>>,
     send_channel: std::sync::mpsc::Sender<BufferCommand>,
-    recv_channel: std::sync::mpsc::Receiver<BufferResult>,
-    // TODO: Implement buffer to allow faster scrolling
-    // buffer: Vec<CaptionedImg>,
-    // buffer_pos: usize,
-    //buffer_size: usize,
+    recv_channel: std::sync::mpsc::Receiver<(BufferResult, Position)>,
+    position: Position,
+    // Names of the images that could not be decoded, they are skipped by the worker
+    failed: Arc<Mutex<HashSet<String>>>,
 }
 
 impl ImageLoader {
-    pub fn new(root_dir: String, file_list: Vec<String>) -> Self {
+    pub fn new(dataset: Dataset, buffer: BufferOptions) -> Self {
+        let file_list = dataset.into_entries();
         // Maybe change this behavour in the future, at the moment is useful for testing
         if file_list.is_empty() {
             panic!("File list empty");
@@ -148,20 +162,24 @@ impl ImageLoader {
 
         let (to_thread, recv_thread) = mpsc::channel();
         let (to_gui, recv_gui) = mpsc::channel();
+        let failed = Arc::new(Mutex::new(HashSet::new()));
 
         let thread_data = WorkerThreadData {
             t_files: file_list,
-            t_dir: root_dir,
+            buffer,
             send_channel: to_gui,
-            recv_channel: recv_thread,
+            prefetch_channel: to_thread.clone(),
+            failed: Arc::clone(&failed),
         };
 
-        let thread_handle = ImageLoader::start_thread(thread_data);
+        let thread_handle = ImageLoader::start_thread(thread_data, recv_thread);
 
         Self {
             send_channel: to_thread,
             recv_channel: recv_gui,
-            _thread_handle: thread_handle,
+            position: Position::default(),
+            failed,
+            thread_handle: Some(thread_handle),
         }
     }
 
@@ -173,18 +191,68 @@ impl ImageLoader {
         self.get_img(false)
     }
 
-    pub fn save(&mut self, img: &CaptionedImg) {
+    // Returns None if there is no image with that name
+    pub fn get_by_name(&mut self, name: &str) -> Option<CaptionedImg> {
+        self.load(BufferCommand::LoadName(name.to_string()))
+    }
+
+    // The index is in the images that can be loaded now, so it depends on the filter
+    pub fn get_at(&mut self, index: usize) -> Option<CaptionedImg> {
+        self.load(BufferCommand::LoadAt(index))
+    }
+
+    // Position of the last loaded image
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    pub fn is_failed(&self, name: &str) -> bool {
+        self.failed.lock().unwrap().contains(name)
+    }
+
+    fn load(&mut self, command: BufferCommand) -> Option<CaptionedImg> {
+        self.send_channel.send(command).unwrap();
+
+        let (img, position) = self.recv_channel.recv().expect("Worker thread closed");
+        self.position = position;
+        if img.is_none() {
+            None
+        } else {
+            Some(img.unwrap())
+        }
+    }
+
+    pub fn set_filter(&mut self, names: Option<HashSet<String>>) {
         self.send_channel
-            .send(BufferCommand::Save(img.name(), img.caption()))
+            .send(BufferCommand::Filter(names))
             .unwrap();
     }
 
-    pub fn save_caption(&mut self, name: &str, caption: &str) {
+    pub fn caption_saved(&mut self, name: &str, caption: &str) {
         self.send_channel
-            .send(BufferCommand::Save(name.to_string(), caption.to_string()))
+            .send(BufferCommand::Saved(name.to_string(), caption.to_string()))
             .unwrap();
     }
 
+    // Reads again the image from disk, after it was modified
+    pub fn reload(&mut self, name: &str) -> Option<CaptionedImg> {
+        self.send_channel
+            .send(BufferCommand::Forget(name.to_string()))
+            .unwrap();
+        self.get_by_name(name)
+    }
+
+    // Waits for the pending saves to be written, the loader can't be used after this
+    pub fn stop(&mut self) {
+        let handle = match self.thread_handle.take() {
+            Some(handle) => handle,
+            None => return,
+        };
+        if self.send_channel.send(BufferCommand::Stop).is_ok() && handle.join().is_err() {
+            println!("Image loader thread panicked");
+        }
+    }
+
     fn get_img(&mut self, forward: bool) -> Option<CaptionedImg> {
         let gen_request = || {
             if forward {
@@ -205,7 +273,8 @@ impl ImageLoader {
         // We ask the thread for the next image, it should already be in ram by now
         self.send_channel.send(gen_request()).unwrap();
 
-        let img = self.recv_channel.recv().expect("Worker thread closed");
+        let (img, position) = self.recv_channel.recv().expect("Worker thread closed");
+        self.position = position;
 
         // Just to be sure
         if !is_correct(&img) {
@@ -219,217 +288,310 @@ impl ImageLoader {
         }
     }
 
-    fn start_thread(data: WorkerThreadData) -> JoinHandle<()> {
+    fn start_thread(
+        data: WorkerThreadData,
+        recv_channel: mpsc::Receiver<BufferCommand>,
+    ) -> JoinHandle<()> {
         thread::spawn(move || {
-            let data = data;
-            // Data unwarap----------------------
-            let t_dir = data.t_dir;
-            let mut t_files = data.t_files;
-            let to_gui = data.send_channel;
-            let recv_channel = data.recv_channel;
-            //-----------------------------------
-
-            let mut pos = 0;
-            const FORWARD: bool = false;
-            const BACKWARD: bool = true;
-            let mut loading_direction = FORWARD;
-            let mut next_img = ImageLoader::load_valid_image(&t_dir, &mut t_files, pos, false);
-            let mut second_img = true;
+            let mut worker = Worker::new(data);
 
             //Main loop
             loop {
                 let command = recv_channel.recv().expect("Main thread shut down");
                 match command {
                     BufferCommand::LoadNext => {
-                        if t_files.is_empty() {
-                            to_gui
-                                .send(BufferResult::None)
-                                .expect("Main therad shut down");
-                            continue;
-                        }
-                        
-                        // This is needed because when the thread has just started and is executing ths code for the first time
-                        // if now we do pos +=1 (pos = 1) and the we load pos+1 (pos = 2) we are skipping the pos=1 image
-                        // the solutions are either init pos to -1 and make it a signed int on this
-                        if !second_img {
-                            pos += 1;
-                        } else {
-                            second_img = false;
-                        }
-
-                        // If the next pos is out of bounds we return None
-                        if pos >= t_files.len() {
-                            pos -= 1;
-                            to_gui
-                                .send(BufferResult::None)
-                                .expect("Main therad shut down");
-                            continue;
-                        }
-
-                        
-                        // If the previous command was also a LoadNext then we already have the next image loaded
-                        let to_send = if loading_direction == FORWARD {
-                            next_img
-                        } else {
-                            // If the previous command was LoadPrevious we need to load the image from disk
-                            // because inside next_image there is the image before the one beeing shown now
-                            ImageLoader::load_valid_image(&t_dir, &mut t_files, pos, FORWARD)
-                        };
-
-                        to_gui
-                            .send(BufferResult::Next(to_send))
-                            .expect("Main therad shut down");
-
-                        // We alreay served the image to the user and now we stat to preload the next image to cut down on loading time
-                        if pos + 1 < t_files.len() {
-                            next_img =
-                                ImageLoader::load_valid_image(&t_dir, &mut t_files, pos + 1, false);
-                        }else{
-                            // In theory wher pos -1 == t_files.len() we just start to send None as a response
-                            // so the image that we are loading now should never be used, but if we do not put anything in next_img
-                            // the compiler gets mad so we do this just to make sure that next_img does not point to memory
-                            // that belongs by now to some other variable
-                            next_img =
-                            ImageLoader::load_valid_image(&t_dir, &mut t_files, pos, false);
-                        }
-
-                        loading_direction = FORWARD;
+                        let index = if worker.started { worker.pos + 1 } else { 0 };
+                        worker.forward = true;
+                        let img = worker.load_at(index, false);
+                        worker.reply(img.map_or(BufferResult::None, BufferResult::Next));
                     }
 
                     BufferCommand::LoadPrevious => {
-                        // If the next pos is out of bounds we return None
-                        if pos == 0 {
-                            to_gui
-                                .send(BufferResult::None)
-                                .expect("Main therad shut down");
+                        // If the previous pos is out of bounds we return None
+                        if !worker.started || worker.pos == 0 {
+                            worker.reply(BufferResult::None);
                             continue;
                         }
+                        worker.forward = false;
+                        let img = worker.load_at(worker.pos - 1, true);
+                        worker.reply(img.map_or(BufferResult::None, BufferResult::Previous));
+                    }
 
-                        if t_files.is_empty() {
-                            to_gui
-                                .send(BufferResult::None)
-                                .expect("Main therad shut down");
-                            continue;
-                        }
-
-                        pos -= 1;
-
-                        /*
-                        // the ownership of next_img is going to be transfered, so if needed we clone it here
-                        let next_next_img = if pos > 0 {
-                            ImageLoader::load_valid_image(&t_dir, &mut t_files, pos - 1, false)
-                        } else {
-                            next_img.clone()
+                    BufferCommand::LoadName(_) | BufferCommand::LoadAt(_) => {
+                        let found = match &command {
+                            BufferCommand::LoadName(name) => {
+                                worker.files.iter().position(|entry| entry.name() == *name)
+                            }
+                            BufferCommand::LoadAt(index) => Some(*index),
+                            _ => unreachable!(),
                         };
-                        */
+                        // If the image is not valid the one after it is sent instead
+                        let img = found.and_then(|index| worker.load_at(index, false));
+                        worker.reply(img.map_or(BufferResult::None, BufferResult::At));
+                    }
 
-                        let to_send = if loading_direction == BACKWARD {
-                            next_img
-                        } else {
-                            ImageLoader::load_valid_image(&t_dir, &mut t_files, pos, BACKWARD)
+                    BufferCommand::Filter(names) => {
+                        worker.files = match names {
+                            Some(names) => worker
+                                .all_files
+                                .iter()
+                                .filter(|entry| names.contains(&entry.name()))
+                                .cloned()
+                                .collect(),
+                            None => worker.all_files.clone(),
                         };
+                        worker.pos = 0;
+                        worker.started = false;
+                        worker.forward = true;
+                    }
 
-                        to_gui
-                            .send(BufferResult::Previous(to_send))
-                            .expect("Main therad shut down");
-
-                        if pos -1 > 0{
-                            next_img = ImageLoader::load_valid_image(&t_dir, &mut t_files, pos - 1, false);
-                        }else{
-                            next_img = ImageLoader::load_valid_image(&t_dir, &mut t_files, pos, false);
-                        }
+                    BufferCommand::Prefetched(name, result) => {
+                        worker.prefetched(name, result);
+                    }
 
-                        loading_direction = BACKWARD;
+                    BufferCommand::Saved(file, tags) => {
+                        worker.buffer.set_caption(&file, &tags);
+                        worker.saved.insert(file, tags);
                     }
 
-                    BufferCommand::Save(file, tags) => {
-                        ImageLoader::save_image(&file, &tags, &t_dir);
+                    BufferCommand::Forget(name) => {
+                        worker.buffer.remove(&name);
                     }
 
                     BufferCommand::Stop => {
                         return;
                     }
                 }
+
+                // Once the gui has its image we start to decode the ones around it
+                worker.prefetch();
             }
         })
     }
 
-    fn try_load_image(root_dir: &String, file_name: &String) -> Option<CaptionedImg> {
-        if root_dir.is_empty() || file_name.is_empty() {
+    fn try_load_image(entry: &DatasetEntry) -> Option<CaptionedImg> {
+        let file_name = entry.name();
+        if file_name.is_empty() {
             println!(
-                "Error trying to load image\nDir: {}\nFile: {}",
-                root_dir, file_name
+                "Error trying to load image\nFile: {}",
+                entry.image_path().display()
             );
             return None;
         }
 
-        let base = if root_dir.ends_with('\\') {
-            root_dir.to_owned() + file_name
-        } else {
-            root_dir.to_owned() + "\\" + file_name
-        };
-
-        let img_path = base.clone() + ".png";
-        let tags_path = base + ".txt";
-
-        let mut buffer = vec![];
-        if let Ok(mut file) = File::open(img_path) {
-            if let Err(e) = file.read_to_end(&mut buffer) {
+        let buffer = match entry.read_image() {
+            Ok(buffer) => buffer,
+            Err(e) => {
                 println!("Error reading file: {}", e);
                 return None;
             }
+        };
+
+        CaptionedImg::new(&file_name, &entry.read_caption(), &buffer)
+    }
+
+    pub fn get_std_img() -> CaptionedImg {
+        let bytes = include_bytes!("../../assets/no_img.png");
+        CaptionedImg::new("no image", ".\\", bytes).unwrap()
+    }
+}
+
+impl Drop for ImageLoader {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// State of the worker thread. The images around the current one are decoded by a pool of
+// threads and kept in the buffer, so moving through the images seldom waits for the disk
+struct Worker {
+    // The images that can be loaded now, only the ones matching the filter
+    files: Vec<DatasetEntry>,
+    all_files: Vec<DatasetEntry>,
+    pos: usize,
+    // False until the first image is loaded, LoadNext then starts from pos 0
+    started: bool,
+    // Direction of the last movement, more images are prefetched in front
+    forward: bool,
+    buffer: ImageBuffer,
+    // Captions saved in this session, the prefetch threads could have read an older one
+    saved: HashMap<String, String>,
+    // Images sent to the prefetch threads and not back yet
+    pending: HashSet<String>,
+    // Images still worth decoding, the prefetch threads skip the others
+    wanted: Arc<Mutex<HashSet<String>>>,
+    jobs: mpsc::Sender<DatasetEntry>,
+    to_gui: mpsc::Sender<(BufferResult, Position)>,
+    // Shared with the gui, so it can leave them out too
+    failed: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Worker {
+    fn new(data: WorkerThreadData) -> Self {
+        let options = data.buffer;
+        let to_worker = data.prefetch_channel;
+        let (jobs, recv_jobs) = mpsc::channel::<DatasetEntry>();
+        let recv_jobs = Arc::new(Mutex::new(recv_jobs));
+        let wanted = Arc::new(Mutex::new(HashSet::new()));
+
+        for _ in 0..options.threads.max(1) {
+            let recv_jobs = Arc::clone(&recv_jobs);
+            let wanted = Arc::clone(&wanted);
+            let to_worker = to_worker.clone();
+            thread::spawn(move || loop {
+                // The lock is released as soon as a job is received
+                let entry = match recv_jobs.lock().unwrap().recv() {
+                    Ok(entry) => entry,
+                    Err(_) => return,
+                };
+                let name = entry.name();
+                let result = if !wanted.lock().unwrap().contains(&name) {
+                    Prefetch::Skipped
+                } else {
+                    match ImageLoader::try_load_image(&entry) {
+                        Some(img) => Prefetch::Loaded(Box::new(img)),
+                        None => Prefetch::Failed,
+                    }
+                };
+                if to_worker
+                    .send(BufferCommand::Prefetched(name, result))
+                    .is_err()
+                {
+                    return;
+                }
+            });
         }
 
-        let read_op = fs::read_to_string(tags_path);
-        let caption = if let Ok(caption) = read_op {
-            caption
-        } else {
-            String::new()
-        };
-        CaptionedImg::new(file_name, &caption, &buffer)
-    }
-
-    // This function returns an image if a valid one is found, otherwise it returns None
-    // also, invalid images are removed from the list
-    fn load_valid_image(
-        root_dir: &String,
-        files: &mut Vec<String>,
-        starting_pos: usize,
-        load_previous: bool,
-    ) -> CaptionedImg {
-        let mut pos = starting_pos;
-        let mut img = ImageLoader::try_load_image(root_dir, &files[pos]);
-        while img.is_none() {
-            files.remove(pos);
-            if files.is_empty() {
-                return ImageLoader::get_std_img();
+        Self {
+            all_files: data.t_files.clone(),
+            files: data.t_files,
+            pos: 0,
+            started: false,
+            forward: true,
+            buffer: ImageBuffer::new(options),
+            saved: HashMap::new(),
+            pending: HashSet::new(),
+            wanted,
+            jobs,
+            to_gui: data.send_channel,
+            failed: data.failed,
+        }
+    }
+
+    fn reply(&self, result: BufferResult) {
+        self.to_gui
+            .send((result, Position::new(self.pos, self.files.len())))
+            .expect("Main therad shut down");
+    }
+
+    // Returns the image at the index, from the buffer if it is there. Invalid images are removed
+    // from the list and the next one is tried, going backward the one before
+    fn load_at(&mut self, index: usize, backward: bool) -> Option<CaptionedImg> {
+        let mut index = index;
+        while index < self.files.len() {
+            let name = self.files[index].name();
+            let img = match self.buffer.get(&name) {
+                Some(img) => Some(img.clone()),
+                None => ImageLoader::try_load_image(&self.files[index]).map(|img| self.fresh(img)),
+            };
+
+            if let Some(img) = img {
+                self.buffer.insert(img.clone());
+                self.pos = index;
+                self.started = true;
+                return Some(img);
             }
 
-            if load_previous && pos > 0 {
-                pos -= 1;
+            self.remove_failed(index);
+            if backward && index > 0 {
+                index -= 1;
             }
+        }
+        None
+    }
 
-            img = ImageLoader::try_load_image(root_dir, &files[pos]);
+    // The image is dropped from every list, so changing the filter does not bring it back
+    fn remove_failed(&mut self, index: usize) {
+        let name = self.files.remove(index).name();
+        self.all_files.retain(|entry| entry.name() != name);
+        if index < self.pos {
+            self.pos -= 1;
         }
-        img.unwrap()
+        self.failed.lock().unwrap().insert(name);
     }
 
-    fn save_image(file_name: &str, caption: &str, root_dir: &str) {
-        let tags_path = root_dir.to_owned() + "\\" + file_name + ".txt";
+    // Uses the caption saved in this session instead of the one read from disk
+    fn fresh(&self, mut img: CaptionedImg) -> CaptionedImg {
+        if let Some(caption) = self.saved.get(&img.name) {
+            img.caption = caption.clone();
+        }
+        img
+    }
 
-        let file = File::create(tags_path);
-        if file.is_err() {
-            println!("Impossible to save");
-            return;
+    fn prefetched(&mut self, name: String, result: Prefetch) {
+        self.pending.remove(&name);
+        match result {
+            Prefetch::Loaded(img) => {
+                if self.wanted.lock().unwrap().contains(&name) && !self.buffer.contains(&name) {
+                    let img = self.fresh(*img);
+                    self.buffer.insert(img);
+                }
+            }
+            Prefetch::Failed => {
+                if let Some(index) = self.files.iter().position(|entry| entry.name() == name) {
+                    self.remove_failed(index);
+                }
+            }
+            Prefetch::Skipped => {}
         }
+    }
 
-        if write!(file.unwrap(), "{}", caption).is_err() {
-            println!("Error saving file");
+    // Images around the current one, the current first and then the nearest ones,
+    // alternating between the two sides
+    fn window(&self) -> Vec<usize> {
+        let options = self.buffer.options();
+        let (ahead, behind) = if self.forward {
+            (options.ahead, options.behind)
+        } else {
+            (options.behind, options.ahead)
+        };
+
+        let mut window = vec![self.pos];
+        for step in 1..=ahead.max(behind) {
+            if step <= ahead && self.pos + step < self.files.len() {
+                window.push(self.pos + step);
+            }
+            if step <= behind && step <= self.pos {
+                window.push(self.pos - step);
+            }
         }
+        window
     }
 
-    pub fn get_std_img() -> CaptionedImg {
-        let bytes = include_bytes!("../../assets/no_img.png");
-        CaptionedImg::new("no image", ".\\", bytes).unwrap()
+    // Drops the images that are not needed anymore and sends the missing ones to the prefetch threads
+    fn prefetch(&mut self) {
+        if self.files.is_empty() {
+            return;
+        }
+
+        let names: Vec<String> = self
+            .window()
+            .into_iter()
+            .map(|index| self.files[index].name())
+            .collect();
+        *self.wanted.lock().unwrap() = names.iter().cloned().collect();
+        self.buffer.evict(&names);
+
+        for (index, name) in self.window().into_iter().zip(names) {
+            if self.buffer.is_full() {
+                break;
+            }
+            if self.buffer.contains(&name) || self.pending.contains(&name) {
+                continue;
+            }
+            if self.jobs.send(self.files[index].clone()).is_ok() {
+                self.pending.insert(name);
+            }
+        }
     }
 }
@@ -0,0 +1,60 @@
+use super::image_loader::Position;
+
+pub enum Jump {
+    Index(usize),
+    Name(String),
+}
+
+// Slider and file name list to go straight to any image instead of clicking Next many times
+pub struct Navigator {
+    // Shown starting from 1
+    index: usize,
+    // The slider keeps its own value while dragged, the image is loaded only when it is released
+    dragging: bool,
+}
+
+impl Navigator {
+    pub fn new() -> Self {
+        Self {
+            index: 1,
+            dragging: false,
+        }
+    }
+
+    // The names are the images that can be reached with the current filter
+    pub fn show<'a>(
+        &mut self,
+        ui: &mut egui::Ui,
+        position: Position,
+        names: impl Iterator<Item = &'a String>,
+        current: &str,
+    ) -> Option<Jump> {
+        let mut jump = None;
+        ui.horizontal(|ui| {
+            if position.len > 0 {
+                if !self.dragging {
+                    self.index = position.index + 1;
+                }
+                let slider = egui::Slider::new(&mut self.index, 1..=position.len)
+                    .text(format!("of {}", position.len));
+                let response = ui.add(slider);
+                self.dragging = response.dragged();
+                if response.drag_released() || (response.changed() && !response.dragged()) {
+                    jump = Some(Jump::Index(self.index - 1));
+                }
+            }
+
+            egui::ComboBox::from_id_source("image_picker")
+                .selected_text(current)
+                .width(300.0)
+                .show_ui(ui, |ui| {
+                    for name in names {
+                        if ui.selectable_label(name == current, name).clicked() {
+                            jump = Some(Jump::Name(name.clone()));
+                        }
+                    }
+                });
+        });
+        jump
+    }
+}
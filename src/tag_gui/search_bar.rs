@@ -0,0 +1,69 @@
+use ai_utils::query::Query;
+
+pub enum SearchEvent {
+    Apply(Query),
+    Clear,
+}
+
+// Text box to restrict Previous and Next to the images whose caption matches a query
+pub struct SearchBar {
+    text: String,
+    error: Option<String>,
+    // Number of images matching the applied query, None if there is no filter
+    pub matches: Option<usize>,
+}
+
+impl SearchBar {
+    pub fn new() -> Self {
+        Self {
+            text: String::new(),
+            error: None,
+            matches: None,
+        }
+    }
+
+    // Moves the keyboard focus to the text box
+    pub fn focus(&self, ctx: &egui::Context) {
+        ctx.memory_mut(|memory| memory.request_focus(egui::Id::new("search_bar")));
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, total: usize) -> Option<SearchEvent> {
+        let mut event = None;
+        ui.horizontal(|ui| {
+            let input = egui::TextEdit::singleline(&mut self.text)
+                .id(egui::Id::new("search_bar"))
+                .hint_text("tag AND NOT (other tag OR /regex/) OR :empty")
+                .desired_width(400.0);
+            let input = ui.add(input);
+            let enter = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+
+            if ui.button("Filter").clicked() || enter {
+                if self.text.trim().is_empty() {
+                    event = Some(SearchEvent::Clear);
+                } else {
+                    match Query::parse(&self.text) {
+                        Ok(query) => {
+                            self.error = None;
+                            event = Some(SearchEvent::Apply(query));
+                        }
+                        Err(e) => self.error = Some(e),
+                    }
+                }
+            }
+
+            let clear = egui::Button::new("Clear");
+            if ui.add_enabled(self.matches.is_some(), clear).clicked() {
+                self.text.clear();
+                self.error = None;
+                event = Some(SearchEvent::Clear);
+            }
+
+            if let Some(error) = &self.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            } else if let Some(matches) = self.matches {
+                ui.weak(format!("{} of {} images", matches, total));
+            }
+        });
+        event
+    }
+}
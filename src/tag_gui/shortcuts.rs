@@ -0,0 +1,318 @@
+use egui::{Key, KeyboardShortcut, ModifierNames, Modifiers};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+const KEYS: [Key; 73] = [
+    Key::ArrowDown,
+    Key::ArrowLeft,
+    Key::ArrowRight,
+    Key::ArrowUp,
+    Key::Escape,
+    Key::Tab,
+    Key::Backspace,
+    Key::Enter,
+    Key::Space,
+    Key::Insert,
+    Key::Delete,
+    Key::Home,
+    Key::End,
+    Key::PageUp,
+    Key::PageDown,
+    Key::Minus,
+    Key::PlusEquals,
+    Key::Num0,
+    Key::Num1,
+    Key::Num2,
+    Key::Num3,
+    Key::Num4,
+    Key::Num5,
+    Key::Num6,
+    Key::Num7,
+    Key::Num8,
+    Key::Num9,
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+    Key::F11,
+    Key::F12,
+    Key::F13,
+    Key::F14,
+    Key::F15,
+    Key::F16,
+    Key::F17,
+    Key::F18,
+    Key::F19,
+    Key::F20,
+];
+
+// Quick tags are bound to the number keys, in order
+const NUMBER_KEYS: [Key; 9] = [
+    Key::Num1,
+    Key::Num2,
+    Key::Num3,
+    Key::Num4,
+    Key::Num5,
+    Key::Num6,
+    Key::Num7,
+    Key::Num8,
+    Key::Num9,
+];
+
+// Keys that move the cursor or edit the text when they are pressed with ctrl in a text box
+const TEXT_EDIT_KEYS: [Key; 14] = [
+    Key::ArrowLeft,
+    Key::ArrowRight,
+    Key::ArrowUp,
+    Key::ArrowDown,
+    Key::Home,
+    Key::End,
+    Key::Backspace,
+    Key::Delete,
+    Key::A,
+    Key::C,
+    Key::K,
+    Key::U,
+    Key::V,
+    Key::W,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    Next,
+    Previous,
+    Save,
+    Undo,
+    Redo,
+    Search,
+    ToggleGrid,
+    Help,
+    // Adds the quick tag to the caption, or removes it if it is already there
+    QuickTag(usize),
+}
+
+impl Action {
+    fn label(&self) -> &'static str {
+        match self {
+            Action::Next => "Next image",
+            Action::Previous => "Previous image",
+            Action::Save => "Save the edited captions",
+            Action::Undo => "Undo the last caption edit",
+            Action::Redo => "Redo the caption edit",
+            Action::Search => "Go to the filter box",
+            Action::ToggleGrid => "Switch between image and grid",
+            Action::Help => "Show this list",
+            Action::QuickTag(_) => "Toggle tag",
+        }
+    }
+}
+
+// Content of the file passed with --shortcuts, the missing actions keep the default keys
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ShortcutFile {
+    next: Option<Vec<String>>,
+    previous: Option<Vec<String>>,
+    save: Option<Vec<String>>,
+    undo: Option<Vec<String>>,
+    redo: Option<Vec<String>>,
+    search: Option<Vec<String>>,
+    grid: Option<Vec<String>>,
+    help: Option<Vec<String>>,
+    quick_tags: Vec<String>,
+}
+
+pub struct Shortcuts {
+    bindings: Vec<(KeyboardShortcut, Action)>,
+    quick_tags: Vec<String>,
+}
+
+impl Default for Shortcuts {
+    fn default() -> Self {
+        let shortcut = KeyboardShortcut::new;
+        Self {
+            bindings: vec![
+                (shortcut(Modifiers::COMMAND, Key::ArrowRight), Action::Next),
+                (shortcut(Modifiers::NONE, Key::PageDown), Action::Next),
+                (
+                    shortcut(Modifiers::COMMAND, Key::ArrowLeft),
+                    Action::Previous,
+                ),
+                (shortcut(Modifiers::NONE, Key::PageUp), Action::Previous),
+                (shortcut(Modifiers::COMMAND, Key::S), Action::Save),
+                (shortcut(Modifiers::COMMAND, Key::Z), Action::Undo),
+                (shortcut(Modifiers::COMMAND, Key::Y), Action::Redo),
+                (
+                    shortcut(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z),
+                    Action::Redo,
+                ),
+                (shortcut(Modifiers::COMMAND, Key::F), Action::Search),
+                (shortcut(Modifiers::COMMAND, Key::G), Action::ToggleGrid),
+                (shortcut(Modifiers::NONE, Key::F1), Action::Help),
+            ],
+            quick_tags: Vec::new(),
+        }
+    }
+}
+
+impl Shortcuts {
+    // The file is json, like {"next": ["Ctrl+Right", "PageDown"], "quick_tags": ["solo", "outdoors"]}
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let file: ShortcutFile = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+        if file.quick_tags.len() > NUMBER_KEYS.len() {
+            return Err(format!("At most {} quick tags", NUMBER_KEYS.len()));
+        }
+
+        let mut shortcuts = Self::default();
+        let configured = [
+            (file.next, Action::Next),
+            (file.previous, Action::Previous),
+            (file.save, Action::Save),
+            (file.undo, Action::Undo),
+            (file.redo, Action::Redo),
+            (file.search, Action::Search),
+            (file.grid, Action::ToggleGrid),
+            (file.help, Action::Help),
+        ];
+        for (keys, action) in configured {
+            if let Some(keys) = keys {
+                shortcuts.bind(action, &keys)?;
+            }
+        }
+        for (i, tag) in file.quick_tags.iter().enumerate() {
+            if tag.trim().is_empty() {
+                return Err("Empty quick tag".to_string());
+            }
+            let shortcut = KeyboardShortcut::new(Modifiers::NONE, NUMBER_KEYS[i]);
+            shortcuts.bindings.push((shortcut, Action::QuickTag(i)));
+            shortcuts.quick_tags.push(tag.trim().to_string());
+        }
+        // The help lists the keys in the order of the actions
+        shortcuts.bindings.sort_by_key(|(_, action)| *action);
+
+        for (i, (shortcut, _)) in shortcuts.bindings.iter().enumerate() {
+            if shortcuts.bindings[..i].iter().any(|(s, _)| s == shortcut) {
+                let name = shortcut.format(&ModifierNames::NAMES, false);
+                return Err(format!("{} is used by more than one action", name));
+            }
+        }
+        Ok(shortcuts)
+    }
+
+    // Replaces the keys of the action
+    fn bind(&mut self, action: Action, keys: &[String]) -> Result<(), String> {
+        self.bindings.retain(|(_, a)| *a != action);
+        for key in keys {
+            self.bindings.push((parse_shortcut(key)?, action));
+        }
+        Ok(())
+    }
+
+    pub fn quick_tag(&self, index: usize) -> &str {
+        &self.quick_tags[index]
+    }
+
+    // Consumes the pressed shortcuts. While a text box has the focus the shortcuts that would
+    // also type or move the cursor are left to the text box
+    pub fn pressed(&self, ctx: &egui::Context) -> Vec<Action> {
+        let typing = ctx.wants_keyboard_input();
+        ctx.input_mut(|input| {
+            self.bindings
+                .iter()
+                .filter(|(shortcut, _)| !typing || !edits_text(shortcut))
+                .filter(|(shortcut, _)| input.consume_shortcut(shortcut))
+                .map(|(_, action)| *action)
+                .collect()
+        })
+    }
+
+    pub fn show_help(&self, ctx: &egui::Context, open: &mut bool) {
+        let is_mac = ctx.os() == egui::os::OperatingSystem::Mac;
+        egui::Window::new("Keyboard shortcuts")
+            .open(open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("shortcuts").striped(true).show(ui, |ui| {
+                    for (shortcut, action) in &self.bindings {
+                        ui.strong(shortcut.format(&ModifierNames::NAMES, is_mac));
+                        match action {
+                            Action::QuickTag(i) => {
+                                ui.label(format!("{} {}", action.label(), self.quick_tags[*i]))
+                            }
+                            _ => ui.label(action.label()),
+                        };
+                        ui.end_row();
+                    }
+                });
+                ui.add_space(5.0);
+                ui.weak("Keys without ctrl or alt do not work while writing in a text box");
+            });
+    }
+}
+
+fn edits_text(shortcut: &KeyboardShortcut) -> bool {
+    let modifiers = shortcut.modifiers;
+    if modifiers.is_none() || modifiers.shift_only() {
+        // Function keys and page up/down are not used by the text boxes
+        let name = shortcut.key.name();
+        let function_key = name.len() > 1 && name.starts_with('F');
+        return !function_key && !matches!(shortcut.key, Key::PageUp | Key::PageDown);
+    }
+    (modifiers.command || modifiers.ctrl) && TEXT_EDIT_KEYS.contains(&shortcut.key)
+}
+
+// Like "Ctrl+Shift+S", ctrl is cmd on mac
+fn parse_shortcut(text: &str) -> Result<KeyboardShortcut, String> {
+    let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+    let key_name = parts.pop().unwrap_or_default();
+    let key = KEYS
+        .iter()
+        .find(|key| key.name().eq_ignore_ascii_case(key_name))
+        .ok_or_else(|| format!("Unknown key \"{}\" in \"{}\"", key_name, text))?;
+
+    let mut modifiers = Modifiers::NONE;
+    for part in parts {
+        modifiers = modifiers.plus(match part.to_lowercase().as_str() {
+            "ctrl" | "cmd" | "command" => Modifiers::COMMAND,
+            "alt" => Modifiers::ALT,
+            "shift" => Modifiers::SHIFT,
+            _ => return Err(format!("Unknown modifier \"{}\" in \"{}\"", part, text)),
+        });
+    }
+    Ok(KeyboardShortcut::new(modifiers, *key))
+}
@@ -0,0 +1,149 @@
+use ai_utils::caption::{Caption, CaptionFormat, Tag};
+use ai_utils::vocabulary::Vocabulary;
+use egui::{CursorIcon, Rect, Sense, Stroke};
+
+const MAX_SUGGESTIONS: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditorMode {
+    Raw,
+    Tags,
+}
+
+// Shows every tag of the caption as a chip that can be removed or dragged to a new position.
+// The caption string is the only state, so the raw text box and the chips never go out of sync
+pub struct TagEditor {
+    dragged: Option<usize>,
+    new_tag: String,
+}
+
+impl TagEditor {
+    pub fn new() -> Self {
+        Self {
+            dragged: None,
+            new_tag: String::new(),
+        }
+    }
+
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        caption: &mut String,
+        vocabulary: &Vocabulary,
+        format: &CaptionFormat,
+    ) {
+        let mut parsed = Caption::parse(caption, format);
+        let tags = match parsed.tags_mut() {
+            Some(tags) => tags,
+            None => {
+                ui.label("This caption is prose, edit it as raw text");
+                return;
+            }
+        };
+
+        let mut changed = false;
+        let mut remove = None;
+        let mut chip_rects: Vec<Rect> = Vec::with_capacity(tags.len());
+
+        ui.horizontal_wrapped(|ui| {
+            for (i, tag) in tags.iter().enumerate() {
+                let being_dragged = self.dragged == Some(i);
+                let chip = egui::Frame::group(ui.style())
+                    .inner_margin(egui::Margin::symmetric(4.0, 2.0))
+                    .show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            let label = egui::Label::new(tag.to_string()).sense(Sense::drag());
+                            let label = ui.add(label).on_hover_cursor(CursorIcon::Grab);
+                            if label.drag_started() {
+                                self.dragged = Some(i);
+                            }
+                            if ui.small_button("x").clicked() {
+                                remove = Some(i);
+                            }
+                        });
+                    });
+                let rect = chip.response.rect;
+                if being_dragged {
+                    ui.painter().rect_stroke(
+                        rect,
+                        2.0,
+                        Stroke::new(2.0, ui.visuals().selection.bg_fill),
+                    );
+                }
+                chip_rects.push(rect);
+            }
+        });
+
+        if let Some(from) = self.dragged {
+            ui.ctx().set_cursor_icon(CursorIcon::Grabbing);
+            let pointer = ui.input(|i| i.pointer.hover_pos());
+            let target =
+                pointer.and_then(|pos| chip_rects.iter().position(|rect| rect.contains(pos)));
+            if let Some(to) = target {
+                if to != from {
+                    ui.painter().rect_stroke(
+                        chip_rects[to],
+                        2.0,
+                        Stroke::new(2.0, ui.visuals().hyperlink_color),
+                    );
+                }
+            }
+
+            if ui.input(|i| i.pointer.any_released()) {
+                self.dragged = None;
+                if let Some(to) = target {
+                    if to != from && from < tags.len() {
+                        let tag = tags.remove(from);
+                        tags.insert(to, tag);
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        if let Some(i) = remove {
+            tags.remove(i);
+            changed = true;
+        }
+
+        ui.add_space(5.0);
+        ui.horizontal(|ui| {
+            let input = ui.add(egui::TextEdit::singleline(&mut self.new_tag).hint_text("New tag"));
+            let enter = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            let add = ui.button("Add").clicked() || enter;
+
+            let mut suggestion = None;
+            ui.horizontal_wrapped(|ui| {
+                for (tag, count) in vocabulary.suggest(&self.new_tag, MAX_SUGGESTIONS) {
+                    if tags.iter().any(|t| t.name == tag) {
+                        continue;
+                    }
+                    if ui.small_button(format!("{} ({})", tag, count)).clicked() {
+                        suggestion = Some(tag);
+                    }
+                }
+            });
+
+            if let Some(tag) = suggestion {
+                tags.push(Tag::new(&tag));
+                self.new_tag.clear();
+                changed = true;
+                input.request_focus();
+            } else if add && !self.new_tag.trim().is_empty() {
+                // More than one tag can be added at once, separated as in the caption
+                for tag in Caption::parse(&self.new_tag, format).tags() {
+                    if !tags.iter().any(|t| t.name == tag.name) {
+                        tags.push(tag.clone());
+                    }
+                }
+                self.new_tag.clear();
+                changed = true;
+                input.request_focus();
+            }
+        });
+
+        if changed {
+            *caption = parsed.serialize(format);
+        }
+    }
+}
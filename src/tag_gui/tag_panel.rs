@@ -0,0 +1,130 @@
+use ai_utils::caption::{Caption, CaptionFormat};
+use ai_utils::vocabulary::Vocabulary;
+
+// Side panel with the number of captions using every tag of the folder.
+// Clicking a tag lists the images that contain it and jumps to the next one
+pub struct TagPanel {
+    pub open: bool,
+    search: String,
+    selected: Option<String>,
+    // Positions of the images containing the selected tag
+    matches: Vec<usize>,
+}
+
+impl TagPanel {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            search: String::new(),
+            selected: None,
+            matches: Vec::new(),
+        }
+    }
+
+    // The captions are the ones saved on disk, as (image name, caption) in folder order.
+    // Returns the image to jump to
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        vocabulary: &Vocabulary,
+        captions: &[(String, String)],
+        current: &str,
+        format: &CaptionFormat,
+    ) -> Option<String> {
+        let mut jump = None;
+        egui::SidePanel::right("tag_panel")
+            .resizable(true)
+            .default_width(250.0)
+            .show_animated(ctx, self.open, |ui| {
+                ui.heading("Tag counts");
+                ui.weak(format!(
+                    "{} tags in {} images",
+                    vocabulary.len(),
+                    captions.len()
+                ));
+                ui.add(egui::TextEdit::singleline(&mut self.search).hint_text("Search"));
+                ui.separator();
+
+                let search = self.search.trim().to_lowercase();
+                let mut clicked = None;
+                egui::ScrollArea::vertical()
+                    .id_source("tag_panel_counts")
+                    .max_height(ui.available_height() * 0.6)
+                    .auto_shrink([false, false])
+                    .show(ui, |ui| {
+                        for (tag, count) in vocabulary.tags_by_count() {
+                            if !tag.to_lowercase().contains(&search) {
+                                continue;
+                            }
+                            let selected = self.selected.as_ref() == Some(&tag);
+                            let label = format!("{} ({})", tag, count);
+                            if ui.selectable_label(selected, label).clicked() {
+                                clicked = Some(tag);
+                            }
+                        }
+                    });
+
+                if let Some(tag) = clicked {
+                    if self.selected.as_ref() == Some(&tag) {
+                        self.selected = None;
+                        self.matches.clear();
+                    } else {
+                        self.selected = Some(tag);
+                        self.refresh(captions, format);
+                        jump = self.next_match(captions, current);
+                    }
+                }
+
+                let tag = match &self.selected {
+                    Some(tag) => tag.clone(),
+                    None => return,
+                };
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} images with {}", self.matches.len(), tag));
+                    if ui.small_button("Next").clicked() {
+                        jump = self.next_match(captions, current);
+                    }
+                });
+                egui::ScrollArea::vertical()
+                    .id_source("tag_panel_matches")
+                    .auto_shrink([false, false])
+                    .show(ui, |ui| {
+                        for name in self.matches.iter().map(|i| &captions[*i].0) {
+                            if ui.selectable_label(name == current, name).clicked() {
+                                jump = Some(name.clone());
+                            }
+                        }
+                    });
+            });
+        jump
+    }
+
+    // Has to be called when a caption is saved, the matches are not recomputed every frame
+    pub fn refresh(&mut self, captions: &[(String, String)], format: &CaptionFormat) {
+        let tag = match &self.selected {
+            Some(tag) => tag,
+            None => return,
+        };
+        self.matches = captions
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, caption))| Caption::parse(caption, format).contains(tag))
+            .map(|(i, _)| i)
+            .collect();
+    }
+
+    // The first match after the current image, starting again from the top at the end of the folder
+    fn next_match(&self, captions: &[(String, String)], current: &str) -> Option<String> {
+        let current = captions.iter().position(|(name, _)| name == current);
+        let next = self
+            .matches
+            .iter()
+            .find(|i| Some(**i) > current)
+            .or_else(|| self.matches.first())?;
+        if Some(*next) == current {
+            return None;
+        }
+        Some(captions[*next].0.clone())
+    }
+}
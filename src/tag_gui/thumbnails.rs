@@ -0,0 +1,135 @@
+use directories::ProjectDirs;
+use egui::{ColorImage, TextureHandle};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+pub const THUMBNAIL_SIZE: u32 = 128;
+const THREADS: usize = 2;
+
+pub enum Thumbnail {
+    Loading,
+    // Texture and size of the original image
+    Ready(TextureHandle, [u32; 2]),
+    Failed,
+}
+
+type Generated = (PathBuf, Option<(ColorImage, [u32; 2])>);
+
+// Thumbnails are generated in background when they are first needed and saved in the cache folder
+// of the user, so opening the same folder again is fast
+pub struct Thumbnails {
+    ctx: egui::Context,
+    thumbnails: HashMap<PathBuf, Thumbnail>,
+    jobs: mpsc::Sender<PathBuf>,
+    results: mpsc::Receiver<Generated>,
+}
+
+impl Thumbnails {
+    pub fn new(ctx: &egui::Context) -> Self {
+        let (jobs, recv_jobs) = mpsc::channel::<PathBuf>();
+        let (send_results, results) = mpsc::channel();
+        let recv_jobs = Arc::new(Mutex::new(recv_jobs));
+
+        for _ in 0..THREADS {
+            let recv_jobs = Arc::clone(&recv_jobs);
+            let send_results = send_results.clone();
+            let ctx = ctx.clone();
+            thread::spawn(move || loop {
+                let path = match recv_jobs.lock().unwrap().recv() {
+                    Ok(path) => path,
+                    Err(_) => return,
+                };
+                let thumbnail = generate(&path);
+                if send_results.send((path, thumbnail)).is_err() {
+                    return;
+                }
+                ctx.request_repaint();
+            });
+        }
+
+        Self {
+            ctx: ctx.clone(),
+            thumbnails: HashMap::new(),
+            jobs,
+            results,
+        }
+    }
+
+    // Starts generating the thumbnail if it was never asked before
+    pub fn get(&mut self, path: &Path) -> &Thumbnail {
+        while let Ok((generated, thumbnail)) = self.results.try_recv() {
+            let thumbnail = match thumbnail {
+                Some((image, size)) => {
+                    let name = generated.to_string_lossy();
+                    let texture = self.ctx.load_texture(name, image, Default::default());
+                    Thumbnail::Ready(texture, size)
+                }
+                None => Thumbnail::Failed,
+            };
+            self.thumbnails.insert(generated, thumbnail);
+        }
+
+        if !self.thumbnails.contains_key(path) {
+            let _ = self.jobs.send(path.to_path_buf());
+            self.thumbnails
+                .insert(path.to_path_buf(), Thumbnail::Loading);
+        }
+        &self.thumbnails[path]
+    }
+
+    // The image changed, its thumbnail is generated again the next time it is needed
+    pub fn forget(&mut self, path: &Path) {
+        self.thumbnails.remove(path);
+    }
+}
+
+fn generate(path: &Path) -> Option<(ColorImage, [u32; 2])> {
+    let (width, height) = image::image_dimensions(path).ok()?;
+    let cached = cache_path(path);
+
+    let thumbnail = match cached.as_ref().and_then(|cached| image::open(cached).ok()) {
+        Some(thumbnail) => thumbnail,
+        None => {
+            let thumbnail = image::open(path)
+                .ok()?
+                .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+            if let Some(cached) = &cached {
+                // Without the cache the thumbnail is simply generated again next time
+                let saved = cached
+                    .parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|_| thumbnail.save(cached).map_err(std::io::Error::other));
+                if let Err(e) = saved {
+                    println!("Error saving thumbnail {}: {}", cached.display(), e);
+                }
+            }
+            thumbnail
+        }
+    };
+
+    let rgba = thumbnail.to_rgba8();
+    let size = [rgba.width() as usize, rgba.height() as usize];
+    let image = ColorImage::from_rgba_unmultiplied(size, rgba.as_raw());
+    Some((image, [width, height]))
+}
+
+// The name depends on the path, size and modification time of the image, so a changed image
+// gets a new thumbnail
+fn cache_path(path: &Path) -> Option<PathBuf> {
+    let dirs = ProjectDirs::from("", "", "ai_utils")?;
+    let metadata = fs::metadata(path).ok()?;
+
+    let mut hasher = DefaultHasher::new();
+    fs::canonicalize(path).ok()?.hash(&mut hasher);
+    metadata.len().hash(&mut hasher);
+    metadata.modified().ok()?.hash(&mut hasher);
+    THUMBNAIL_SIZE.hash(&mut hasher);
+
+    let name = format!("{:016x}.png", hasher.finish());
+    Some(dirs.cache_dir().join("thumbnails").join(name))
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::caption::{Caption, CaptionFormat};
+use crate::dataset::Dataset;
+
+// Every tag used in the dataset, with the number of captions that contain it.
+// Tags from an imported list are suggested only after the ones already used in the dataset
+#[derive(Clone, Debug, Default)]
+pub struct Vocabulary {
+    counts: HashMap<String, usize>,
+    imported: HashMap<String, usize>,
+}
+
+impl Vocabulary {
+    pub fn from_dataset(dataset: &Dataset, format: &CaptionFormat) -> Self {
+        let mut vocabulary = Vocabulary::default();
+        for file in dataset.caption_files() {
+            if let Ok(text) = fs::read_to_string(file) {
+                vocabulary.add_caption(&Caption::parse(&text, format));
+            }
+        }
+        vocabulary
+    }
+
+    pub fn add_caption(&mut self, caption: &Caption) {
+        for tag in caption.tags() {
+            *self.counts.entry(tag.name.clone()).or_insert(0) += 1;
+        }
+    }
+
+    pub fn remove_caption(&mut self, caption: &Caption) {
+        for tag in caption.tags() {
+            if let Some(count) = self.counts.get_mut(&tag.name) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(&tag.name);
+                }
+            }
+        }
+    }
+
+    // Tag lists like the danbooru one are csv files with the tag in the first column
+    // and optionally the number of posts in the third one, returns the number of imported tags
+    pub fn import_csv(&mut self, path: &Path) -> io::Result<usize> {
+        let content = fs::read_to_string(path)?;
+        let mut imported = 0;
+        for line in content.lines() {
+            let columns: Vec<&str> = line.split(',').map(|c| c.trim()).collect();
+            let name = columns[0].trim_matches('"');
+            if name.is_empty() {
+                continue;
+            }
+            let count = columns
+                .get(2)
+                .and_then(|c| c.trim_matches('"').parse::<usize>().ok());
+            // A header line has no numeric count
+            if count.is_none() && columns.len() > 2 {
+                continue;
+            }
+            self.imported.insert(name.to_string(), count.unwrap_or(0));
+            imported += 1;
+        }
+        Ok(imported)
+    }
+
+    pub fn count(&self, tag: &str) -> usize {
+        self.counts.get(tag).copied().unwrap_or(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    // The tags of the dataset, most used first
+    pub fn tags_by_count(&self) -> Vec<(String, usize)> {
+        let mut tags: Vec<(String, usize)> = self
+            .counts
+            .iter()
+            .map(|(tag, count)| (tag.clone(), *count))
+            .collect();
+        tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        tags
+    }
+
+    // Tags starting with the prefix, spaces and underscores are considered the same so "blue e"
+    // also finds "blue_eyes". Tags of the dataset come first, then the imported ones, each sorted by count
+    pub fn suggest(&self, prefix: &str, limit: usize) -> Vec<(String, usize)> {
+        let prefix = normalize(prefix);
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+
+        let mut suggestions: Vec<(String, usize)> = self
+            .tags_by_count()
+            .into_iter()
+            .filter(|(tag, _)| normalize(tag).starts_with(&prefix))
+            .collect();
+
+        let mut imported: Vec<(String, usize)> = self
+            .imported
+            .iter()
+            .filter(|(tag, _)| normalize(tag).starts_with(&prefix))
+            // The same tag could already be in the dataset written in another way
+            .filter(|(tag, _)| {
+                !suggestions
+                    .iter()
+                    .any(|(used, _)| normalize(used) == normalize(tag))
+            })
+            .map(|(tag, count)| (tag.clone(), *count))
+            .collect();
+        imported.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        suggestions.extend(imported);
+        suggestions.truncate(limit);
+        suggestions
+    }
+
+    pub fn is_imported(&self, tag: &str) -> bool {
+        !self.counts.contains_key(tag) && self.imported.contains_key(tag)
+    }
+}
+
+fn normalize(tag: &str) -> String {
+    tag.trim().to_lowercase().replace('_', " ")
+}